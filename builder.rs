@@ -2,7 +2,11 @@ use crate::hash;
 use anyhow::{anyhow, Result};
 use ignore::Walk;
 use rayon::prelude::*;
-use std::path::PathBuf;
+use std::{
+	collections::HashSet,
+	io::Write,
+	path::{Path, PathBuf},
+};
 
 pub struct BuildOptions {
 	pub workspace_path: PathBuf,
@@ -34,7 +38,7 @@ pub fn build(options: BuildOptions) -> Result<()> {
 		.iter()
 		.map(|client_crate_manifest_path| {
 			let client_crate_manifest =
-				std::fs::read_to_string(&options.workspace_path.join(client_crate_manifest_path))?;
+				std::fs::read_to_string(options.workspace_path.join(client_crate_manifest_path))?;
 			let client_crate_manifest: toml::Value = toml::from_str(&client_crate_manifest)?;
 			let client_crate_package_name = client_crate_manifest
 				.as_table()
@@ -51,38 +55,83 @@ pub fn build(options: BuildOptions) -> Result<()> {
 			Ok(client_crate_package_name)
 		})
 		.collect::<Result<Vec<_>>>()?;
-	client_crate_package_names
+	let client_dir = options.crate_out_dir.join("client");
+	let client_paths = client_crate_package_names
 		.par_iter()
-		.for_each(|client_crate_package_name| {
+		.map(|client_crate_package_name| {
 			let hash = hash(client_crate_package_name);
 			let input_path = std::env::var(format!(
 				"CARGO_BIN_FILE_{}",
 				client_crate_package_name.to_uppercase()
 			))
 			.unwrap();
-			let output_path = js_dir.join(format!("{}_bg.wasm", hash));
+			let output_path = client_dir.join(format!("{}_bg.wasm", hash));
 			// Do not re-run wasm-bindgen if the output wasm exists and is not older than the input wasm.
 			let input_metadata = std::fs::metadata(&input_path).unwrap();
 			let input_modified_time = input_metadata.modified().unwrap();
-			if let Ok(output_wasm_metadata) = std::fs::metadata(&output_path) {
-				let output_modified_time = output_wasm_metadata.modified().unwrap();
-				if input_modified_time <= output_modified_time {
-					return;
-				}
+			let up_to_date = std::fs::metadata(&output_path)
+				.map(|output_wasm_metadata| {
+					input_modified_time <= output_wasm_metadata.modified().unwrap()
+				})
+				.unwrap_or(false);
+			if !up_to_date {
+				wasm_bindgen_cli_support::Bindgen::new()
+					.web(true)
+					.unwrap()
+					.keep_debug(profile == "debug")
+					.omit_default_module_path(false)
+					.remove_name_section(true)
+					.remove_producers_section(true)
+					.input_path(input_path)
+					.out_name(&hash)
+					.generate(&client_dir)
+					.map_err(|error| anyhow!(error))
+					.unwrap();
 			}
-			wasm_bindgen_cli_support::Bindgen::new()
-				.web(true)
-				.unwrap()
-				.keep_debug(profile == "debug")
-				.omit_default_module_path(false)
-				.remove_name_section(true)
-				.remove_producers_section(true)
-				.input_path(input_path)
-				.out_name(&hash)
-				.generate(&js_dir)
-				.map_err(|error| anyhow!(error))
-				.unwrap();
-		});
+			// Name the js and wasm by the hash of their contents.
+			let wasm = std::fs::read(&output_path)?;
+			let wasm_file_name = format!("{}.wasm", crate::hash(&wasm));
+			let js = std::fs::read_to_string(client_dir.join(format!("{}.js", hash)))?;
+			let js = js.replace(
+				&format!("'{}_bg.wasm'", hash),
+				&format!("'{}'", wasm_file_name),
+			);
+			let js_file_name = format!("{}.js", crate::hash(&js));
+			write_if_missing(&js_dir.join(&js_file_name), js.as_bytes())?;
+			write_if_missing(&js_dir.join(&wasm_file_name), &wasm)?;
+			Ok((
+				client_crate_package_name.clone(),
+				js_file_name,
+				wasm_file_name,
+			))
+		})
+		.collect::<Result<Vec<_>>>()?;
+	let client_snippets_dir = client_dir.join("snippets");
+	if client_snippets_dir.exists() {
+		copy_if_newer(&client_snippets_dir, &js_dir.join("snippets"))?;
+	}
+	// Write the client paths manifest.
+	let mut client_paths_manifest = toml::value::Table::new();
+	let mut js_file_names = HashSet::new();
+	for (client_crate_package_name, js_file_name, wasm_file_name) in client_paths {
+		let mut paths = toml::value::Table::new();
+		paths.insert(
+			"path_js".to_owned(),
+			toml::Value::String(format!("/js/{}", js_file_name)),
+		);
+		paths.insert(
+			"path_wasm".to_owned(),
+			toml::Value::String(format!("/js/{}", wasm_file_name)),
+		);
+		client_paths_manifest.insert(client_crate_package_name, toml::Value::Table(paths));
+		js_file_names.insert(js_file_name);
+		js_file_names.insert(wasm_file_name);
+	}
+	let client_paths_manifest = toml::to_string(&toml::Value::Table(client_paths_manifest))?;
+	std::fs::write(
+		options.crate_out_dir.join("client_paths.toml"),
+		client_paths_manifest,
+	)?;
 	// Collect CSS.
 	let mut css = String::new();
 	for dir in options.css_paths {
@@ -97,26 +146,34 @@ pub fn build(options: BuildOptions) -> Result<()> {
 	std::fs::write(output_dir.join("styles.css"), css).unwrap();
 	// Copy static files.
 	let static_dir = options.crate_path.join("static");
-	for entry in Walk::new(&static_dir) {
-		let entry = entry.unwrap();
-		let input_path = entry.path();
-		if !input_path.is_file() {
-			continue;
-		}
-		let output_path = output_dir.join(input_path.strip_prefix(&static_dir).unwrap());
-		let input_metadata = std::fs::metadata(&input_path).unwrap();
-		let input_modified_time = input_metadata.modified().unwrap();
-		if let Ok(output_metadata) = std::fs::metadata(&output_path) {
-			let output_modified_time = output_metadata.modified().unwrap();
-			if input_modified_time <= output_modified_time {
+	copy_if_newer(&static_dir, &output_dir)?;
+	// Copy prerendered pages in release builds.
+	let prerendered_dir = options.crate_path.join(PRERENDERED_DIR);
+	let prerendered_output_dir = output_dir.join(PRERENDERED_DIR);
+	if profile == "release" && prerendered_dir.exists() {
+		copy_if_newer(&prerendered_dir, &prerendered_output_dir)?;
+	}
+	if prerendered_output_dir.exists() {
+		for entry in Walk::new(&prerendered_output_dir) {
+			let entry = entry?;
+			let output_path = entry.path();
+			if !output_path.is_file() {
 				continue;
 			}
+			let path = output_path.strip_prefix(&prerendered_output_dir).unwrap();
+			let path = match path.extension().and_then(|extension| extension.to_str()) {
+				Some("br") | Some("gz") => path.with_extension(""),
+				_ => path.to_owned(),
+			};
+			if profile != "release" || !prerendered_dir.join(path).is_file() {
+				std::fs::remove_file(output_path)?;
+			}
 		}
-		std::fs::create_dir_all(output_path.parent().unwrap()).unwrap();
-		std::fs::copy(input_path, output_path).unwrap();
 	}
-	// Copy assets.
+	// Copy assets, naming each by the hash of its contents, and write the asset manifest.
 	let asset_extensions = &["gif", "jpg", "png", "svg", "woff2"];
+	let mut asset_manifest = toml::value::Table::new();
+	let mut asset_file_names = HashSet::new();
 	for entry in Walk::new(&options.crate_path) {
 		let entry = entry.unwrap();
 		let input_path = entry.path();
@@ -132,18 +189,150 @@ pub fn build(options: BuildOptions) -> Result<()> {
 			continue;
 		}
 		let asset_path = input_path.strip_prefix(&options.workspace_path).unwrap();
-		let hash = hash(asset_path.to_str().unwrap().as_bytes());
-		let output_path = assets_dir.join(&format!("{}.{}", hash, extension));
-		let input_metadata = std::fs::metadata(&input_path).unwrap();
-		let input_modified_time = input_metadata.modified().unwrap();
+		let data = std::fs::read(input_path)?;
+		let hash = hash(&data);
+		let output_file_name = format!("{}.{}", hash, extension);
+		asset_manifest.insert(
+			asset_path.to_str().unwrap().to_owned(),
+			toml::Value::String(format!("/assets/{}", output_file_name)),
+		);
+		asset_file_names.insert(output_file_name.clone());
+		write_if_missing(&assets_dir.join(&output_file_name), &data)?;
+	}
+	let asset_manifest = toml::to_string(&toml::Value::Table(asset_manifest))?;
+	std::fs::write(options.crate_out_dir.join("assets.toml"), asset_manifest)?;
+	// Remove assets and client files that are no longer in the manifests.
+	remove_unlisted_files(&assets_dir, &asset_file_names, &static_dir.join("assets"))?;
+	remove_unlisted_files(&js_dir, &js_file_names, &static_dir.join("js"))?;
+	// Precompress output files in release builds and remove stale compressed variants.
+	let precompress_outputs = profile == "release";
+	let compressible_extensions = &["css", "html", "js", "json", "svg", "txt", "wasm", "xml"];
+	let mut compressible_paths = Vec::new();
+	// Keep compressed files copied from the static directory.
+	let is_variant = |path: &Path| {
+		let extension = path.with_extension("");
+		let extension = extension
+			.extension()
+			.and_then(|extension| extension.to_str());
+		let is_compressible = extension
+			.map(|extension| compressible_extensions.contains(&extension))
+			.unwrap_or(false);
+		let static_path = static_dir.join(path.strip_prefix(&output_dir).unwrap());
+		is_compressible && !static_path.exists()
+	};
+	for entry in Walk::new(&output_dir) {
+		let entry = entry.unwrap();
+		let path = entry.path();
+		if !path.is_file() {
+			continue;
+		}
+		let extension = path.extension().map(|e| e.to_str().unwrap());
+		match extension {
+			Some("gz") | Some("br") if is_variant(path) => {
+				if !precompress_outputs || !path.with_extension("").is_file() {
+					std::fs::remove_file(path)?;
+				}
+			}
+			Some(extension)
+				if precompress_outputs && compressible_extensions.contains(&extension) =>
+			{
+				compressible_paths.push(path.to_owned());
+			}
+			_ => continue,
+		}
+	}
+	compressible_paths
+		.par_iter()
+		.map(|input_path| precompress(input_path))
+		.collect::<Result<()>>()?;
+	Ok(())
+}
+
+pub(crate) const PRERENDERED_DIR: &str = "prerendered";
+
+pub(crate) const PRERENDERED_BUILD_ID_PATH: &str = "build_id.txt";
+
+fn copy_if_newer(input_dir: &Path, output_dir: &Path) -> Result<()> {
+	for entry in Walk::new(input_dir) {
+		let entry = entry?;
+		let input_path = entry.path();
+		if !input_path.is_file() {
+			continue;
+		}
+		let output_path = output_dir.join(input_path.strip_prefix(input_dir).unwrap());
+		let input_metadata = std::fs::metadata(input_path)?;
+		let input_modified_time = input_metadata.modified()?;
 		if let Ok(output_metadata) = std::fs::metadata(&output_path) {
-			let output_modified_time = output_metadata.modified().unwrap();
+			let output_modified_time = output_metadata.modified()?;
 			if input_modified_time <= output_modified_time {
 				continue;
 			}
 		}
-		std::fs::create_dir_all(output_path.parent().unwrap()).unwrap();
-		std::fs::copy(input_path, output_path).unwrap();
+		std::fs::create_dir_all(output_path.parent().unwrap())?;
+		std::fs::copy(input_path, output_path)?;
 	}
 	Ok(())
 }
+
+fn write_if_missing(path: &Path, data: &[u8]) -> Result<()> {
+	if !path.exists() {
+		std::fs::write(path, data)?;
+	}
+	Ok(())
+}
+
+fn remove_unlisted_files(
+	dir: &Path,
+	file_names: &HashSet<String>,
+	static_dir: &Path,
+) -> Result<()> {
+	for entry in std::fs::read_dir(dir)? {
+		let path = entry?.path();
+		if !path.is_file() {
+			continue;
+		}
+		let file_name = path.file_name().unwrap().to_str().unwrap();
+		let listed_file_name = file_name
+			.strip_suffix(".gz")
+			.or_else(|| file_name.strip_suffix(".br"))
+			.unwrap_or(file_name);
+		if !file_names.contains(listed_file_name) && !static_dir.join(file_name).exists() {
+			std::fs::remove_file(path)?;
+		}
+	}
+	Ok(())
+}
+
+fn precompress(input_path: &Path) -> Result<()> {
+	let gzip_path = variant_path(input_path, "gz");
+	let brotli_path = variant_path(input_path, "br");
+	// Do not recompress if both variants exist and are not older than the input.
+	let input_modified_time = std::fs::metadata(input_path)?.modified()?;
+	let up_to_date = [&gzip_path, &brotli_path].iter().all(|path| {
+		std::fs::metadata(path)
+			.and_then(|metadata| metadata.modified())
+			.map(|output_modified_time| input_modified_time <= output_modified_time)
+			.unwrap_or(false)
+	});
+	if up_to_date {
+		return Ok(());
+	}
+	let data = std::fs::read(input_path)?;
+	let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+	gzip.write_all(&data)?;
+	std::fs::write(gzip_path, gzip.finish()?)?;
+	let mut brotli = Vec::new();
+	{
+		let mut writer = brotli::CompressorWriter::new(&mut brotli, 4096, 11, 22);
+		writer.write_all(&data)?;
+	}
+	std::fs::write(brotli_path, brotli)?;
+	Ok(())
+}
+
+pub(crate) fn variant_path(path: &Path, extension: &str) -> PathBuf {
+	let mut variant_path = path.as_os_str().to_owned();
+	variant_path.push(".");
+	variant_path.push(extension);
+	PathBuf::from(variant_path)
+}
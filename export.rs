@@ -0,0 +1,678 @@
+use crate::{
+	builder::{PRERENDERED_BUILD_ID_PATH, PRERENDERED_DIR},
+	sitemap::sitemap_xml,
+	Route, RouteParams, Sunfish,
+};
+use futures::{FutureExt, StreamExt};
+use ignore::Walk;
+use rayon::prelude::*;
+use std::{
+	collections::HashSet,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+#[derive(Debug, Default)]
+pub struct ExportOptions {
+	/// The maximum number of pages to render at once. If this is `None`, it is the number of cpus.
+	pub concurrency: Option<usize>,
+	/// If this is true, export keeps going after a failure and reports every failure together. Otherwise it stops at the first failure.
+	pub collect_errors: bool,
+}
+
+/// An error exporting a single file or page.
+#[derive(Debug)]
+pub struct ExportError {
+	pub operation: ExportOperation,
+	/// The path of the file the operation failed on, if any.
+	pub file_path: Option<PathBuf>,
+	/// The path with placeholders of the route being exported, if any.
+	pub route: Option<String>,
+	/// The path of the page being rendered, if any.
+	pub path: Option<String>,
+	pub source: anyhow::Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportOperation {
+	CreateRuntime,
+	Walk,
+	Read,
+	Write,
+	CreateDirectory,
+	RemoveFile,
+	RemoveDirectory,
+	GetPaths,
+	Render,
+}
+
+/// The errors from an export. This always has at least one error.
+#[derive(Debug)]
+pub struct ExportErrors(pub Vec<ExportError>);
+
+impl Sunfish {
+	/// Export the static files and static pages to `dist_path`. This creates a runtime to render the pages on, so it must not be called from within a runtime. Use `export_async` instead in that case.
+	pub fn export(&self, out_dir: &Path, dist_path: &Path) -> Result<(), ExportErrors> {
+		let runtime = tokio::runtime::Runtime::new()
+			.map_err(|error| ExportError::new(ExportOperation::CreateRuntime, error))?;
+		runtime.block_on(self.export_async(out_dir, dist_path))
+	}
+
+	/// Export the static files and static pages to `dist_path`. Files whose contents have not changed since the last export are left alone, and files that are no longer exported are deleted.
+	pub async fn export_async(&self, out_dir: &Path, dist_path: &Path) -> Result<(), ExportErrors> {
+		let mut errors = Errors::new(self.export_options.collect_errors);
+		let output_path = out_dir.join("output");
+		// Copy the contents of the out_dir to the dist_path.
+		let copy_dist_path = dist_path.to_owned();
+		let mut exported_paths =
+			spawn_blocking(&mut errors, ExportOperation::Write, move |errors| {
+				copy_output(&output_path, &copy_dist_path, errors)
+			})
+			.await?;
+		let pages = self.static_pages(dist_path, &mut errors).await?;
+		let mut sitemap_entries = Vec::new();
+		for page in pages.iter() {
+			exported_paths.insert(page.output_html_path.clone());
+			if let Some(entry) = self.sitemap_entry(&page.route_path, &page.route, &page.path) {
+				sitemap_entries.push((self.trailing_slash.canonical_path(&page.path), entry));
+			}
+		}
+		self.render_pages(pages, &mut errors).await?;
+		// Write sitemap.xml and robots.txt.
+		let mut generated_files = Vec::new();
+		if let Some(sitemap_options) = self.sitemap.as_ref() {
+			let sitemap = sitemap_xml(&sitemap_options.base_url, &sitemap_entries);
+			generated_files.push(("sitemap.xml", sitemap));
+		}
+		if let Some(robots_txt) = self.robots_txt() {
+			generated_files.push(("robots.txt", robots_txt));
+		}
+		for (path, _) in generated_files.iter() {
+			exported_paths.insert(dist_path.join(path));
+		}
+		let generated_dist_path = dist_path.to_owned();
+		spawn_blocking(&mut errors, ExportOperation::Write, move |errors| {
+			for (path, data) in generated_files {
+				let output_path = generated_dist_path.join(path);
+				if let Err(error) = write_if_changed(&output_path, data.as_bytes()) {
+					errors.push(error)?;
+				}
+			}
+			Ok(())
+		})
+		.await?;
+		// Delete the files and directories left over from previous exports.
+		let dist_path = dist_path.to_owned();
+		spawn_blocking(&mut errors, ExportOperation::RemoveFile, move |errors| {
+			remove_stale_files(&dist_path, &exported_paths, errors)
+		})
+		.await?;
+		errors.finish()
+	}
+
+	/// Render the static pages to `prerendered_path`. This must not be called from within a runtime. Use `prerender_async` instead in that case.
+	pub fn prerender(&self, prerendered_path: &Path) -> Result<(), ExportErrors> {
+		let runtime = tokio::runtime::Runtime::new()
+			.map_err(|error| ExportError::new(ExportOperation::CreateRuntime, error))?;
+		runtime.block_on(self.prerender_async(prerendered_path))
+	}
+
+	/// Render the static pages to `prerendered_path` along with the app's `build_id`.
+	pub async fn prerender_async(&self, prerendered_path: &Path) -> Result<(), ExportErrors> {
+		let mut errors = Errors::new(self.export_options.collect_errors);
+		let pages = self.static_pages(prerendered_path, &mut errors).await?;
+		let mut prerendered_paths = pages
+			.iter()
+			.map(|page| page.output_html_path.clone())
+			.collect::<HashSet<_>>();
+		let build_id_path = prerendered_path.join(PRERENDERED_BUILD_ID_PATH);
+		prerendered_paths.insert(build_id_path.clone());
+		let build_id = self.build_id.clone();
+		spawn_blocking(&mut errors, ExportOperation::Write, move |_| {
+			write_if_changed(&build_id_path, build_id.as_bytes())?;
+			Ok(())
+		})
+		.await?;
+		self.render_pages(pages, &mut errors).await?;
+		let prerendered_path = prerendered_path.to_owned();
+		spawn_blocking(&mut errors, ExportOperation::RemoveFile, move |errors| {
+			remove_stale_files(&prerendered_path, &prerendered_paths, errors)
+		})
+		.await?;
+		errors.finish()
+	}
+
+	/// Get the pages for each static route, with their html written under `dist_path`.
+	async fn static_pages(
+		&self,
+		dist_path: &Path,
+		errors: &mut Errors,
+	) -> Result<Vec<Page>, ExportErrors> {
+		let mut pages = Vec::new();
+		for route_initializer in self.routes.iter() {
+			let route_path = &route_initializer.path_with_placeholders;
+			let route = (route_initializer.init)();
+			let paths = match &route {
+				Route::Static {
+					paths: Some(paths), ..
+				} => match paths().await {
+					Ok(paths) => paths,
+					Err(error) => {
+						errors.push(
+							ExportError::new(ExportOperation::GetPaths, error).route(route_path),
+						)?;
+						continue;
+					}
+				},
+				Route::Static { paths: None, .. } => vec![route_path.clone()],
+				Route::Dynamic { .. } => continue,
+			};
+			let route = Arc::new(route);
+			for path in paths {
+				let params = RouteParams::from_path(route_path, &path);
+				// Static hosts serve 404.html and 500.html as the error pages, regardless of the trailing slash policy.
+				let output_html_path = if self.is_error_route(route_path) {
+					format!("{}.html", route_path.trim_start_matches('/'))
+				} else {
+					self.trailing_slash.output_html_path(&path)
+				};
+				let output_html_path = dist_path.join(output_html_path);
+				let canonical_path = self.trailing_slash.canonical_path(&path);
+				pages.push(Page {
+					route: route.clone(),
+					route_path: route_path.clone(),
+					path,
+					canonical_path,
+					params,
+					output_html_path,
+				});
+			}
+		}
+		Ok(pages)
+	}
+
+	/// Render and write the html for each page, rendering up to the export concurrency pages at once.
+	async fn render_pages(
+		&self,
+		pages: Vec<Page>,
+		errors: &mut Errors,
+	) -> Result<(), ExportErrors> {
+		let concurrency = self
+			.export_options
+			.concurrency
+			.unwrap_or_else(rayon::current_num_threads);
+		let mut results = futures::stream::iter(pages)
+			.map(|page| {
+				let route_path = page.route_path.clone();
+				let path = page.path.clone();
+				tokio::spawn(render(page)).map(move |result| {
+					result.map_err(|error| {
+						ExportError::new(ExportOperation::Render, error).page(&route_path, &path)
+					})?
+				})
+			})
+			.buffer_unordered(concurrency);
+		while let Some(result) = results.next().await {
+			if let Err(error) = result {
+				errors.push(error)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+struct Page {
+	route: Arc<Route>,
+	route_path: String,
+	path: String,
+	/// The path `Sunfish::handle` passes to the handler for this page, which is the canonical form of `path`.
+	canonical_path: String,
+	params: RouteParams,
+	output_html_path: PathBuf,
+}
+
+async fn render(page: Page) -> Result<(), ExportError> {
+	let handler = match page.route.as_ref() {
+		Route::Static { handler, .. } => handler,
+		Route::Dynamic { .. } => unreachable!(),
+	};
+	let html = handler(page.canonical_path.clone(), page.params)
+		.await
+		.map_err(|error| {
+			ExportError::new(ExportOperation::Render, error).page(&page.route_path, &page.path)
+		})?;
+	let output_html_path = page.output_html_path.clone();
+	let result =
+		tokio::task::spawn_blocking(move || write_if_changed(&output_html_path, html.as_bytes()))
+			.await
+			.unwrap_or_else(|error| {
+				Err(ExportError::new(ExportOperation::Write, error)
+					.file_path(&page.output_html_path))
+			});
+	result.map_err(|error| error.page(&page.route_path, &page.path))
+}
+
+/// Copy the files in `output_path` to `dist_path`, in parallel, and return the paths they were copied to.
+fn copy_output(
+	output_path: &Path,
+	dist_path: &Path,
+	errors: &mut Errors,
+) -> Result<HashSet<PathBuf>, ExportErrors> {
+	std::fs::create_dir_all(dist_path).map_err(|error| {
+		ExportError::new(ExportOperation::CreateDirectory, error).file_path(dist_path)
+	})?;
+	let mut files = Vec::new();
+	for entry in Walk::new(output_path) {
+		let entry = match entry {
+			Ok(entry) => entry,
+			Err(error) => {
+				errors
+					.push(ExportError::new(ExportOperation::Walk, error).file_path(output_path))?;
+				continue;
+			}
+		};
+		let input_path = entry.path();
+		if !input_path.is_file() {
+			continue;
+		}
+		let path = input_path.strip_prefix(output_path).unwrap();
+		// Prerendered pages are exported by rendering the static routes.
+		if path.starts_with(PRERENDERED_DIR) {
+			continue;
+		}
+		files.push((input_path.to_owned(), dist_path.join(path)));
+	}
+	let copy = |(input_path, output_path): &(PathBuf, PathBuf)| {
+		let data = std::fs::read(input_path).map_err(|error| {
+			ExportError::new(ExportOperation::Read, error).file_path(input_path)
+		})?;
+		write_if_changed(output_path, &data)
+	};
+	if errors.collect_errors {
+		let copy_errors = files
+			.par_iter()
+			.filter_map(|file| copy(file).err())
+			.collect::<Vec<_>>();
+		errors.extend(copy_errors);
+	} else {
+		files.par_iter().try_for_each(copy)?;
+	}
+	let copied_paths = files
+		.into_iter()
+		.map(|(_, output_path)| output_path)
+		.collect();
+	Ok(copied_paths)
+}
+
+/// Run `f`, which does blocking filesystem work, on the blocking thread pool so it does not stall the runtime the export is running on. If `f` panics, the export fails with an error for `operation`.
+async fn spawn_blocking<T, F>(
+	errors: &mut Errors,
+	operation: ExportOperation,
+	f: F,
+) -> Result<T, ExportErrors>
+where
+	T: 'static + Send,
+	F: 'static + Send + FnOnce(&mut Errors) -> Result<T, ExportErrors>,
+{
+	let collect_errors = errors.collect_errors;
+	let result = tokio::task::spawn_blocking(move || {
+		let mut task_errors = Errors::new(collect_errors);
+		let result = f(&mut task_errors);
+		(result, task_errors.errors)
+	})
+	.await;
+	match result {
+		Ok((result, task_errors)) => {
+			errors.extend(task_errors);
+			result
+		}
+		Err(error) => {
+			let mut errors = std::mem::replace(errors, Errors::new(collect_errors)).errors;
+			errors.push(ExportError::new(operation, error));
+			Err(ExportErrors(errors))
+		}
+	}
+}
+
+/// Write `data` to `path` unless the file at `path` already has the same contents, so unchanged files keep their modified time.
+fn write_if_changed(path: &Path, data: &[u8]) -> Result<(), ExportError> {
+	if let Ok(existing_data) = std::fs::read(path) {
+		if existing_data == data {
+			return Ok(());
+		}
+	}
+	let parent_path = path.parent().unwrap();
+	std::fs::create_dir_all(parent_path).map_err(|error| {
+		ExportError::new(ExportOperation::CreateDirectory, error).file_path(parent_path)
+	})?;
+	std::fs::write(path, data)
+		.map_err(|error| ExportError::new(ExportOperation::Write, error).file_path(path))?;
+	Ok(())
+}
+
+fn remove_stale_files(
+	dist_path: &Path,
+	exported_paths: &HashSet<PathBuf>,
+	errors: &mut Errors,
+) -> Result<(), ExportErrors> {
+	// Visit the contents of each directory before the directory itself, so directories that become empty can be removed.
+	for entry in walkdir::WalkDir::new(dist_path)
+		.min_depth(1)
+		.contents_first(true)
+	{
+		let entry = match entry {
+			Ok(entry) => entry,
+			Err(error) => {
+				errors.push(ExportError::new(ExportOperation::Walk, error).file_path(dist_path))?;
+				continue;
+			}
+		};
+		let path = entry.path();
+		if entry.file_type().is_dir() {
+			let is_empty = std::fs::read_dir(path)
+				.map(|mut entries| entries.next().is_none())
+				.unwrap_or(false);
+			if is_empty {
+				if let Err(error) = std::fs::remove_dir(path) {
+					errors.push(
+						ExportError::new(ExportOperation::RemoveDirectory, error).file_path(path),
+					)?;
+				}
+			}
+		} else if !exported_paths.contains(path) {
+			if let Err(error) = std::fs::remove_file(path) {
+				errors
+					.push(ExportError::new(ExportOperation::RemoveFile, error).file_path(path))?;
+			}
+		}
+	}
+	Ok(())
+}
+
+/// The errors collected so far in an export. If errors are not being collected, `push` returns the first error so it can be propagated with `?`.
+struct Errors {
+	collect_errors: bool,
+	errors: Vec<ExportError>,
+}
+
+impl Errors {
+	fn new(collect_errors: bool) -> Errors {
+		Errors {
+			collect_errors,
+			errors: Vec::new(),
+		}
+	}
+
+	fn push(&mut self, error: ExportError) -> Result<(), ExportErrors> {
+		if self.collect_errors {
+			self.errors.push(error);
+			Ok(())
+		} else {
+			Err(ExportErrors(vec![error]))
+		}
+	}
+
+	fn extend(&mut self, errors: Vec<ExportError>) {
+		self.errors.extend(errors);
+	}
+
+	fn finish(self) -> Result<(), ExportErrors> {
+		if self.errors.is_empty() {
+			Ok(())
+		} else {
+			Err(ExportErrors(self.errors))
+		}
+	}
+}
+
+impl ExportError {
+	fn new(operation: ExportOperation, source: impl Into<anyhow::Error>) -> ExportError {
+		ExportError {
+			operation,
+			file_path: None,
+			route: None,
+			path: None,
+			source: source.into(),
+		}
+	}
+
+	fn file_path(mut self, file_path: &Path) -> ExportError {
+		self.file_path = Some(file_path.to_owned());
+		self
+	}
+
+	fn route(mut self, route: &str) -> ExportError {
+		self.route = Some(route.to_owned());
+		self
+	}
+
+	fn page(mut self, route: &str, path: &str) -> ExportError {
+		self.route = Some(route.to_owned());
+		self.path = Some(path.to_owned());
+		self
+	}
+}
+
+impl std::fmt::Display for ExportError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "failed to {}", self.operation)?;
+		if let Some(file_path) = &self.file_path {
+			write!(f, " \"{}\"", file_path.display())?;
+		}
+		match (&self.route, &self.path) {
+			(Some(route), Some(path)) => write!(
+				f,
+				" while exporting page \"{}\" of route \"{}\"",
+				path, route
+			)?,
+			(Some(route), None) => write!(f, " while exporting route \"{}\"", route)?,
+			_ => {}
+		}
+		write!(f, ": {}", self.source)
+	}
+}
+
+// The message includes the source's message, so the source is not also returned from `source`, which would print it twice in error chains.
+impl std::error::Error for ExportError {}
+
+impl std::fmt::Display for ExportOperation {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let operation = match self {
+			ExportOperation::CreateRuntime => "create the runtime",
+			ExportOperation::Walk => "walk",
+			ExportOperation::Read => "read",
+			ExportOperation::Write => "write",
+			ExportOperation::CreateDirectory => "create the directory",
+			ExportOperation::RemoveFile => "remove the file",
+			ExportOperation::RemoveDirectory => "remove the directory",
+			ExportOperation::GetPaths => "get the paths",
+			ExportOperation::Render => "render",
+		};
+		write!(f, "{}", operation)
+	}
+}
+
+impl std::fmt::Display for ExportErrors {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self.0.as_slice() {
+			[error] => write!(f, "{}", error),
+			errors => {
+				write!(f, "{} errors occurred while exporting", errors.len())?;
+				for error in errors {
+					write!(f, "\n{}", error)?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+impl std::error::Error for ExportErrors {}
+
+impl From<ExportError> for ExportErrors {
+	fn from(error: ExportError) -> ExportErrors {
+		ExportErrors(vec![error])
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::RouteInitializer;
+
+	fn test_dir(name: &str) -> PathBuf {
+		let path =
+			std::env::temp_dir().join(format!("sunfish_export_{}_{}", std::process::id(), name));
+		if path.exists() {
+			std::fs::remove_dir_all(&path).unwrap();
+		}
+		std::fs::create_dir_all(&path).unwrap();
+		path
+	}
+
+	fn modified(path: &Path) -> std::time::SystemTime {
+		std::fs::metadata(path).unwrap().modified().unwrap()
+	}
+
+	fn sunfish(routes: Vec<RouteInitializer>, collect_errors: bool) -> Sunfish {
+		Sunfish {
+			output: crate::include_dir::IncludeDir::Included(
+				crate::include_dir::IncludedDirectory(Default::default()),
+			),
+			routes_handler: Box::new(|_| async { Ok(None) }.boxed()),
+			routes,
+			cache_control: None,
+			content_types: Default::default(),
+			not_found_route: None,
+			error_route: None,
+			middleware: Vec::new(),
+			export_options: ExportOptions {
+				concurrency: None,
+				collect_errors,
+			},
+			sitemap: None,
+			robots_txt: None,
+			trailing_slash: Default::default(),
+			build_id: String::new(),
+		}
+	}
+
+	fn route(path_with_placeholders: &str, init: fn() -> Route) -> RouteInitializer {
+		RouteInitializer {
+			path_with_placeholders: path_with_placeholders.to_owned(),
+			init,
+		}
+	}
+
+	fn ok_route() -> Route {
+		Route::new_static(|_| "ok".to_owned())
+	}
+
+	fn path_route() -> Route {
+		Route::new_static_with_paths(|| vec!["/users/1".to_owned()], |path| path)
+	}
+
+	fn render_error_route() -> Route {
+		Route::new_static_async(|_, _| async { Err(anyhow::anyhow!("render failed")) }.boxed())
+	}
+
+	fn paths_error_route() -> Route {
+		Route::new_static_with_paths_async(
+			|| async { Err(anyhow::anyhow!("paths failed")) }.boxed(),
+			|_, _| async { Ok(String::new()) }.boxed(),
+		)
+	}
+
+	fn routes() -> Vec<RouteInitializer> {
+		vec![
+			route("/", ok_route),
+			route("/render", render_error_route),
+			route("/users/[id]", paths_error_route),
+		]
+	}
+
+	#[test]
+	fn test_collect_errors() {
+		let path = test_dir("collect_errors");
+		let errors = sunfish(routes(), true).prerender(&path).unwrap_err();
+		let mut errors = errors
+			.0
+			.iter()
+			.map(|error| error.to_string())
+			.collect::<Vec<_>>();
+		errors.sort();
+		assert_eq!(
+			errors,
+			vec![
+				"failed to get the paths while exporting route \"/users/[id]\": paths failed",
+				"failed to render while exporting page \"/render\" of route \"/render\": render failed",
+			]
+		);
+		assert_eq!(std::fs::read(path.join("index.html")).unwrap(), b"ok");
+		std::fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn test_stop_at_first_error() {
+		let path = test_dir("stop_at_first_error");
+		let errors = sunfish(routes(), false).prerender(&path).unwrap_err();
+		assert_eq!(errors.0.len(), 1);
+		assert_eq!(errors.0[0].operation, ExportOperation::GetPaths);
+		std::fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn test_handler_gets_canonical_path() {
+		let path = test_dir("handler_gets_canonical_path");
+		let mut sunfish = sunfish(vec![route("/users/[id]", path_route)], false);
+		sunfish.trailing_slash = crate::TrailingSlash::Always;
+		sunfish.prerender(&path).unwrap();
+		let html = std::fs::read_to_string(path.join("users").join("1").join("index.html"));
+		assert_eq!(html.unwrap(), "/users/1/");
+		std::fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn test_error_chain_prints_source_once() {
+		let error = ExportError::new(ExportOperation::Render, anyhow::anyhow!("render failed"))
+			.page("/render", "/render");
+		let error = anyhow::Error::from(ExportErrors::from(error));
+		assert_eq!(
+			format!("{:#}", error),
+			"failed to render while exporting page \"/render\" of route \"/render\": render failed"
+		);
+	}
+
+	#[test]
+	fn test_write_if_changed_keeps_modified_time() {
+		let path = test_dir("write_if_changed");
+		let file_path = path.join("dir").join("index.html");
+		write_if_changed(&file_path, b"hello").unwrap();
+		let first_modified = modified(&file_path);
+		std::thread::sleep(std::time::Duration::from_millis(20));
+		write_if_changed(&file_path, b"hello").unwrap();
+		assert_eq!(modified(&file_path), first_modified);
+		write_if_changed(&file_path, b"goodbye").unwrap();
+		assert_ne!(modified(&file_path), first_modified);
+		assert_eq!(std::fs::read(&file_path).unwrap(), b"goodbye");
+		std::fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn test_remove_stale_files() {
+		let path = test_dir("remove_stale_files");
+		let kept_path = path.join("docs").join("index.html");
+		let stale_path = path.join("docs").join("old.html");
+		let stale_dir_path = path.join("old").join("nested");
+		write_if_changed(&kept_path, b"kept").unwrap();
+		write_if_changed(&stale_path, b"stale").unwrap();
+		write_if_changed(&stale_dir_path.join("index.html"), b"stale").unwrap();
+		let exported_paths = vec![kept_path.clone()].into_iter().collect();
+		let mut errors = Errors::new(false);
+		remove_stale_files(&path, &exported_paths, &mut errors).unwrap();
+		assert!(kept_path.exists());
+		assert!(!stale_path.exists());
+		assert!(!path.join("old").exists());
+		std::fs::remove_dir_all(path).unwrap();
+	}
+}
@@ -1,8 +1,23 @@
+use anyhow::Result;
+use futures::{Stream, TryStreamExt};
+use hyper::body::Bytes;
+use once_cell::sync::{Lazy, OnceCell};
 use std::{
 	borrow::Cow,
-	collections::BTreeMap,
+	collections::{BTreeMap, HashMap},
+	io::{Read, SeekFrom},
+	ops::{Bound, Range},
 	path::{Path, PathBuf},
+	pin::Pin,
+	sync::Mutex,
+	time::SystemTime,
 };
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Rerun the build script when any file in the directory at `path` changes.
+pub fn rerun_if_changed(path: impl AsRef<Path>) {
+	println!("cargo:rerun-if-changed={}", path.as_ref().display());
+}
 
 pub enum IncludeDir {
 	Fs(FsDirectory),
@@ -21,16 +36,102 @@ impl IncludeDir {
 			IncludeDir::Included(s) => s.read(path),
 		}
 	}
+
+	/// Iterate over the files, in order of their paths relative to the root.
+	pub fn iter(&self) -> Iter<'_> {
+		self.list(Path::new(""))
+	}
+
+	/// Iterate over the files under `prefix`, in order of their paths.
+	pub fn list(&self, prefix: &Path) -> Iter<'_> {
+		let prefix = match normalize(prefix) {
+			Some(prefix) => prefix,
+			None => return Iter::Empty,
+		};
+		match self {
+			IncludeDir::Fs(s) => s.list(prefix),
+			IncludeDir::Included(s) => s.list(prefix),
+		}
+	}
+
+	/// Read the files from `path` instead of the directory passed to `include_dir`.
+	pub fn set_fs_path(&mut self, path: impl Into<PathBuf>) {
+		if let IncludeDir::Fs(s) = self {
+			s.path = path.into();
+		}
+	}
+
+	pub fn contains(&self, path: &Path) -> bool {
+		self.read(path).is_some()
+	}
+
+	/// Get the number of files.
+	pub fn len(&self) -> usize {
+		match self {
+			IncludeDir::Fs(_) => self.iter().filter(Result::is_ok).count(),
+			IncludeDir::Included(s) => s.0.len(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl<'a> IntoIterator for &'a IncludeDir {
+	type Item = Result<(PathBuf, FsOrIncludedFile)>;
+	type IntoIter = Iter<'a>;
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+/// An iterator over the files in an `IncludeDir`, which yields their paths relative to the root.
+pub enum Iter<'a> {
+	Fs {
+		root: &'a Path,
+		walk: Box<ignore::Walk>,
+	},
+	Included {
+		prefix: PathBuf,
+		range: std::collections::btree_map::Range<'a, &'static Path, IncludedFile>,
+	},
+	Empty,
+}
+
+impl<'a> Iterator for Iter<'a> {
+	type Item = Result<(PathBuf, FsOrIncludedFile)>;
+	fn next(&mut self) -> Option<Self::Item> {
+		match self {
+			Iter::Fs { root, walk } => next_fs_file(root, walk),
+			Iter::Included { prefix, range } => {
+				let (path, file) = range.next()?;
+				if !path.starts_with(prefix) {
+					*self = Iter::Empty;
+					return None;
+				}
+				Some(Ok((
+					path.to_path_buf(),
+					FsOrIncludedFile::Included(file.clone()),
+				)))
+			}
+			Iter::Empty => None,
+		}
+	}
 }
 
 impl IntoIterator for IncludeDir {
-	type Item = (PathBuf, FsOrIncludedFile);
+	type Item = Result<(PathBuf, FsOrIncludedFile)>;
 	type IntoIter = FsOrIncludedIntoIter;
 	fn into_iter(self) -> Self::IntoIter {
 		match self {
-			IncludeDir::Fs(fs) => FsOrIncludedIntoIter::Fs(
-				walkdir::WalkDir::new(fs.0).sort_by_file_name().into_iter(),
-			),
+			IncludeDir::Fs(fs) => {
+				let walk = Box::new(fs.walk_builder().build());
+				FsOrIncludedIntoIter::Fs {
+					root: fs.path,
+					walk,
+				}
+			}
 			IncludeDir::Included(embedded) => {
 				FsOrIncludedIntoIter::Included(embedded.0.into_iter())
 			}
@@ -38,99 +139,582 @@ impl IntoIterator for IncludeDir {
 	}
 }
 
+/// Like `Iter`, but for an `IncludeDir` that is consumed.
 pub enum FsOrIncludedIntoIter {
-	Fs(walkdir::IntoIter),
+	Fs {
+		root: PathBuf,
+		walk: Box<ignore::Walk>,
+	},
 	Included(std::collections::btree_map::IntoIter<&'static Path, IncludedFile>),
 }
 
 impl Iterator for FsOrIncludedIntoIter {
-	type Item = (PathBuf, FsOrIncludedFile);
+	type Item = Result<(PathBuf, FsOrIncludedFile)>;
 	fn next(&mut self) -> Option<Self::Item> {
 		match self {
-			FsOrIncludedIntoIter::Fs(walkdir) => loop {
-				let entry = match walkdir.next() {
-					None => return None,
-					Some(Err(e)) => panic!("{}", e),
-					Some(Ok(entry)) => entry,
-				};
-				if entry.file_type().is_file() {
-					let path = entry.path().to_owned();
-					return Some((path.clone(), FsOrIncludedFile::Fs(FsFile(path))));
-				} else {
-					continue;
-				}
-			},
+			FsOrIncludedIntoIter::Fs { root, walk } => next_fs_file(root, walk),
 			FsOrIncludedIntoIter::Included(map) => map
 				.next()
-				.map(|(path, file)| (path.to_owned(), FsOrIncludedFile::Included(file))),
+				.map(|(path, file)| Ok((path.to_owned(), FsOrIncludedFile::Included(file)))),
+		}
+	}
+}
+
+/// Get the next file from a walk of an `FsDirectory`, skipping directories and symlinks.
+fn next_fs_file(
+	root: &Path,
+	walk: &mut ignore::Walk,
+) -> Option<Result<(PathBuf, FsOrIncludedFile)>> {
+	loop {
+		let entry = match walk.next()? {
+			Ok(entry) => entry,
+			Err(error) => return Some(Err(error.into())),
+		};
+		if entry.file_type().map(|file_type| file_type.is_file()) == Some(true) {
+			let path = entry.into_path();
+			let relative_path = path.strip_prefix(root).unwrap().to_owned();
+			return Some(Ok((relative_path, FsOrIncludedFile::Fs(FsFile(path)))));
 		}
 	}
 }
 
 impl FsOrIncludedFile {
-	pub fn data(&self) -> Cow<'static, [u8]> {
+	/// Get the file's data.
+	pub fn data(&self) -> std::io::Result<Cow<'static, [u8]>> {
 		match self {
 			FsOrIncludedFile::Fs(s) => s.data(),
-			FsOrIncludedFile::Included(s) => s.data(),
+			FsOrIncludedFile::Included(s) => Ok(s.data()),
 		}
 	}
 
-	pub fn hash(&self) -> Option<&'static str> {
+	/// Get the hash of the file's contents.
+	pub fn hash(&self) -> std::io::Result<Cow<'static, str>> {
 		match self {
 			FsOrIncludedFile::Fs(s) => s.hash(),
-			FsOrIncludedFile::Included(s) => s.hash(),
+			FsOrIncludedFile::Included(s) => Ok(s.hash()),
+		}
+	}
+
+	pub fn metadata(&self) -> std::io::Result<Metadata> {
+		match self {
+			FsOrIncludedFile::Fs(s) => s.metadata(),
+			FsOrIncludedFile::Included(s) => Ok(s.metadata()),
+		}
+	}
+
+	pub fn len(&self) -> std::io::Result<u64> {
+		Ok(self.metadata()?.len)
+	}
+
+	pub fn is_empty(&self) -> std::io::Result<bool> {
+		Ok(self.len()? == 0)
+	}
+
+	pub fn modified(&self) -> std::io::Result<Option<SystemTime>> {
+		Ok(self.metadata()?.modified)
+	}
+
+	/// Stream the file's data in chunks without reading it all into memory.
+	pub fn stream(&self) -> FileStream {
+		match self.metadata() {
+			Ok(metadata) => self.stream_range(0..metadata.len),
+			Err(error) => Box::pin(futures::stream::once(async move { Err(error) })),
+		}
+	}
+
+	/// Stream the bytes of the file in `range`, which must be within the file.
+	pub fn stream_range(&self, range: Range<u64>) -> FileStream {
+		match self {
+			FsOrIncludedFile::Fs(s) => s.stream_range(range),
+			FsOrIncludedFile::Included(s) => s.stream_range(range),
 		}
 	}
 }
 
-pub struct FsDirectory(pub PathBuf);
+pub type FileStream = Pin<Box<dyn Send + Stream<Item = std::io::Result<Bytes>>>>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+	/// The length of the file in bytes.
+	pub len: u64,
+	/// The time the file was last modified.
+	pub modified: Option<SystemTime>,
+}
+
+/// A directory that is read from the filesystem.
+pub struct FsDirectory {
+	pub path: PathBuf,
+	/// If this is not empty, only files matching at least one of these globs are included.
+	pub include: Vec<String>,
+	/// Files matching any of these globs are excluded, even if they match an `include` glob.
+	pub exclude: Vec<String>,
+}
 
 impl FsDirectory {
+	/// Read the file at `path` relative to the root, or return `None` if it is not a file that iterating would yield.
 	pub fn read(&self, path: &Path) -> Option<FsOrIncludedFile> {
-		let path = self.0.join(path);
-		if path.exists() {
-			Some(FsOrIncludedFile::Fs(FsFile(path)))
-		} else {
-			None
+		let path = self.path.join(normalize_file_path(path)?);
+		let root = self.path.canonicalize().ok()?;
+		let canonical_path = path.canonicalize().ok()?;
+		if !canonical_path.starts_with(&root) || !canonical_path.is_file() {
+			return None;
+		}
+		// Walk only the directories leading to the file.
+		let target = path.clone();
+		let included = self
+			.walk_builder()
+			.filter_entry(move |entry| target.starts_with(entry.path()))
+			.build()
+			.filter_map(Result::ok)
+			.any(|entry| {
+				entry.path() == path
+					&& entry.file_type().map(|file_type| file_type.is_file()) == Some(true)
+			});
+		if !included {
+			return None;
+		}
+		Some(FsOrIncludedFile::Fs(FsFile(path)))
+	}
+
+	fn list(&self, prefix: PathBuf) -> Iter<'_> {
+		// Walk the whole directory so ignore files above the prefix apply.
+		let root = self.path.clone();
+		let walk = self
+			.walk_builder()
+			.filter_entry(move |entry| {
+				let path = entry.path().strip_prefix(&root).unwrap();
+				path.starts_with(&prefix) || prefix.starts_with(path)
+			})
+			.build();
+		Iter::Fs {
+			root: &self.path,
+			walk: Box::new(walk),
+		}
+	}
+
+	/// Configure a walk of the directory. This must match the walk in the `include_dir` macro.
+	fn walk_builder(&self) -> ignore::WalkBuilder {
+		let mut overrides = ignore::overrides::OverrideBuilder::new(&self.path);
+		for glob in self.include.iter() {
+			overrides.add(glob).unwrap();
+		}
+		for glob in self.exclude.iter() {
+			overrides.add(&format!("!{}", glob)).unwrap();
 		}
+		let mut walk_builder = ignore::WalkBuilder::new(&self.path);
+		walk_builder
+			.overrides(overrides.build().unwrap())
+			.parents(false)
+			.require_git(false)
+			.git_global(false)
+			.git_exclude(false)
+			.sort_by_file_name(|a, b| a.cmp(b));
+		walk_builder
 	}
 }
 
 pub struct FsFile(pub PathBuf);
 
 impl FsFile {
-	pub fn data(&self) -> Cow<'static, [u8]> {
-		Cow::Owned(std::fs::read(&self.0).unwrap())
+	pub fn data(&self) -> std::io::Result<Cow<'static, [u8]>> {
+		Ok(Cow::Owned(std::fs::read(&self.0)?))
+	}
+
+	/// Hash the file's contents, caching the hash until the file changes.
+	pub fn hash(&self) -> std::io::Result<Cow<'static, str>> {
+		let metadata = self.metadata()?;
+		if let Some(cached) = FS_FILE_HASHES.lock().unwrap().get(&self.0) {
+			if cached.len == metadata.len && cached.modified == metadata.modified {
+				return Ok(Cow::Owned(cached.hash.clone()));
+			}
+		}
+		let hash = crate::hash_reader(std::fs::File::open(&self.0)?)?;
+		FS_FILE_HASHES.lock().unwrap().insert(
+			self.0.clone(),
+			FsFileHash {
+				len: metadata.len,
+				modified: metadata.modified,
+				hash: hash.clone(),
+			},
+		);
+		Ok(Cow::Owned(hash))
+	}
+
+	pub fn metadata(&self) -> std::io::Result<Metadata> {
+		let metadata = std::fs::metadata(&self.0)?;
+		Ok(Metadata {
+			len: metadata.len(),
+			modified: metadata.modified().ok(),
+		})
 	}
 
-	pub fn hash(&self) -> Option<&'static str> {
-		None
+	pub fn stream_range(&self, range: Range<u64>) -> FileStream {
+		let path = self.0.clone();
+		let file = async move {
+			let mut file = tokio::fs::File::open(path).await?;
+			file.seek(SeekFrom::Start(range.start)).await?;
+			Ok::<_, std::io::Error>(file.take(range.end - range.start))
+		};
+		let stream = futures::stream::once(file)
+			.map_ok(|file| {
+				futures::stream::try_unfold(file, |mut file| async move {
+					let mut chunk = vec![0; FS_FILE_CHUNK_SIZE];
+					let len = file.read(&mut chunk).await?;
+					if len == 0 {
+						return Ok(None);
+					}
+					chunk.truncate(len);
+					Ok(Some((Bytes::from(chunk), file)))
+				})
+			})
+			.try_flatten();
+		Box::pin(stream)
 	}
 }
 
+const FS_FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+static FS_FILE_HASHES: Lazy<Mutex<HashMap<PathBuf, FsFileHash>>> = Lazy::new(Default::default);
+
+struct FsFileHash {
+	len: u64,
+	modified: Option<SystemTime>,
+	hash: String,
+}
+
 #[derive(Debug)]
 pub struct IncludedDirectory(pub BTreeMap<&'static Path, IncludedFile>);
 
 #[derive(Clone, Debug)]
 pub struct IncludedFile {
-	pub data: &'static [u8],
+	pub data: IncludedData,
 	pub hash: &'static str,
+	pub modified: Option<SystemTime>,
+}
+
+/// The bytes of an included file, which may be stored compressed.
+#[derive(Clone, Copy, Debug)]
+pub enum IncludedData {
+	Uncompressed(&'static [u8]),
+	Compressed {
+		compression: Compression,
+		data: &'static [u8],
+		/// The length of the file before it was compressed.
+		len: u64,
+		decompressed: &'static Decompressed,
+	},
+}
+
+/// The decompressed data of a file that is stored compressed, decompressed on first read.
+#[derive(Debug, Default)]
+pub struct Decompressed(OnceCell<Vec<u8>>);
+
+impl Decompressed {
+	pub const fn new() -> Decompressed {
+		Decompressed(OnceCell::new())
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+	Gzip,
+	Brotli,
+}
+
+impl Compression {
+	/// Get the value of the `Content-Encoding` header for data compressed with this compression.
+	pub fn content_encoding(self) -> &'static str {
+		match self {
+			Compression::Gzip => "gzip",
+			Compression::Brotli => "br",
+		}
+	}
 }
 
 impl IncludedDirectory {
 	pub fn read(&self, path: &Path) -> Option<FsOrIncludedFile> {
 		self.0
-			.get(path)
+			.get(normalize_file_path(path)?.as_path())
 			.map(|file| FsOrIncludedFile::Included(file.clone()))
 	}
+
+	fn list(&self, prefix: PathBuf) -> Iter<'_> {
+		// Paths are sorted, so the paths under the prefix are contiguous.
+		let range = self
+			.0
+			.range::<Path, _>((Bound::Included(prefix.as_path()), Bound::Unbounded));
+		Iter::Included { prefix, range }
+	}
 }
 
 impl IncludedFile {
+	/// Get the file's uncompressed data, decompressing it on first read.
 	pub fn data(&self) -> Cow<'static, [u8]> {
-		Cow::Borrowed(self.data)
+		Cow::Borrowed(self.static_data())
+	}
+
+	fn static_data(&self) -> &'static [u8] {
+		match self.data {
+			IncludedData::Uncompressed(data) => data,
+			IncludedData::Compressed {
+				compression,
+				data,
+				decompressed,
+				..
+			} => decompressed
+				.0
+				.get_or_init(|| decompress(compression, data))
+				.as_slice(),
+		}
+	}
+
+	/// Get the file's compressed data and its compression, or `None` if the file is stored uncompressed.
+	pub fn compressed_data(&self) -> Option<(Compression, &'static [u8])> {
+		match self.data {
+			IncludedData::Uncompressed(_) => None,
+			IncludedData::Compressed {
+				compression, data, ..
+			} => Some((compression, data)),
+		}
+	}
+
+	pub fn hash(&self) -> Cow<'static, str> {
+		Cow::Borrowed(self.hash)
+	}
+
+	pub fn metadata(&self) -> Metadata {
+		let len = match self.data {
+			IncludedData::Uncompressed(data) => data.len() as u64,
+			IncludedData::Compressed { len, .. } => len,
+		};
+		Metadata {
+			len,
+			modified: self.modified,
+		}
+	}
+
+	pub fn stream_range(&self, range: Range<u64>) -> FileStream {
+		let data = self.static_data();
+		let data = Bytes::from_static(&data[range.start as usize..range.end as usize]);
+		Box::pin(futures::stream::once(async move { Ok(data) }))
+	}
+}
+
+/// Decompress the data of a compressed included file. This is called at most once per file, by its `Decompressed`.
+fn decompress(compression: Compression, data: &[u8]) -> Vec<u8> {
+	let mut decompressed = Vec::new();
+	match compression {
+		Compression::Gzip => flate2::read::GzDecoder::new(data)
+			.read_to_end(&mut decompressed)
+			.unwrap(),
+		Compression::Brotli => brotli::Decompressor::new(data, 4096)
+			.read_to_end(&mut decompressed)
+			.unwrap(),
+	};
+	decompressed
+}
+
+/// Normalize the path of a file, which must not end with a slash, because a file is not a directory.
+fn normalize_file_path(path: &Path) -> Option<PathBuf> {
+	let ends_with_separator = path
+		.to_string_lossy()
+		.chars()
+		.last()
+		.map(std::path::is_separator)
+		.unwrap_or(false);
+	if ends_with_separator {
+		return None;
+	}
+	normalize(path)
+}
+
+/// Normalize a path relative to the root, or return `None` if it has `..` components or is absolute.
+fn normalize(path: &Path) -> Option<PathBuf> {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			std::path::Component::Normal(component) => normalized.push(component),
+			std::path::Component::CurDir => {}
+			std::path::Component::ParentDir
+			| std::path::Component::RootDir
+			| std::path::Component::Prefix(_) => return None,
+		}
+	}
+	Some(normalized)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Create a test directory with `root` and `outside` directories.
+	fn test_dir(name: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!(
+			"sunfish_include_dir_{}_{}",
+			std::process::id(),
+			name
+		));
+		if path.exists() {
+			std::fs::remove_dir_all(&path).unwrap();
+		}
+		std::fs::create_dir_all(path.join("root").join("dir")).unwrap();
+		std::fs::create_dir_all(path.join("outside")).unwrap();
+		std::fs::write(path.join("root").join("a.txt"), "a").unwrap();
+		std::fs::write(path.join("root").join("dir").join("b.txt"), "b").unwrap();
+		std::fs::write(path.join("outside").join("secret.txt"), "secret").unwrap();
+		path
+	}
+
+	fn fs_directory(path: &Path) -> FsDirectory {
+		FsDirectory {
+			path: path.join("root"),
+			include: Vec::new(),
+			exclude: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn test_read_file() {
+		let path = test_dir("read_file");
+		let directory = fs_directory(&path);
+		let file = directory.read(Path::new("dir/b.txt")).unwrap();
+		assert_eq!(file.data().unwrap().as_ref(), b"b");
+		assert_eq!(file.hash().unwrap(), crate::hash(b"b"));
+		assert!(directory.read(Path::new("./a.txt")).is_some());
+		assert!(directory.read(Path::new("missing.txt")).is_none());
+		std::fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn test_read_parent_dir() {
+		let path = test_dir("read_parent_dir");
+		let directory = fs_directory(&path);
+		assert!(directory.read(Path::new("../outside/secret.txt")).is_none());
+		assert!(directory.read(Path::new("dir/../a.txt")).is_none());
+		std::fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn test_read_absolute_path() {
+		let path = test_dir("read_absolute_path");
+		let directory = fs_directory(&path);
+		assert!(directory.read(&path.join("root").join("a.txt")).is_none());
+		assert!(directory
+			.read(&path.join("outside").join("secret.txt"))
+			.is_none());
+		std::fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn test_read_directory() {
+		let path = test_dir("read_directory");
+		let directory = fs_directory(&path);
+		assert!(directory.read(Path::new("dir")).is_none());
+		assert!(directory.read(Path::new("")).is_none());
+		assert!(directory.read(Path::new("a.txt/")).is_none());
+		std::fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn test_fs_and_included_directories_match() {
+		let path = test_dir("fs_and_included_directories_match");
+		std::fs::create_dir_all(path.join("root").join("dir2")).unwrap();
+		std::fs::write(path.join("root").join("dir2").join("c.txt"), "c").unwrap();
+		let fs = IncludeDir::Fs(fs_directory(&path));
+		let files = [("a.txt", b"a"), ("dir/b.txt", b"b"), ("dir2/c.txt", b"c")]
+			.iter()
+			.map(|(path, data)| {
+				let file = IncludedFile {
+					data: IncludedData::Uncompressed(data.as_ref()),
+					hash: "0123456789abcdef",
+					modified: None,
+				};
+				(Path::new(*path), file)
+			})
+			.collect();
+		let included = IncludeDir::Included(IncludedDirectory(files));
+		let list = |directory: &IncludeDir, prefix: &str| {
+			directory
+				.list(Path::new(prefix))
+				.map(|entry| entry.unwrap().0)
+				.collect::<Vec<_>>()
+		};
+		for prefix in [
+			"",
+			".",
+			"dir",
+			"dir/",
+			"./dir",
+			"dir/b.txt",
+			"a.txt",
+			"missing",
+			"../",
+		] {
+			assert_eq!(list(&fs, prefix), list(&included, prefix), "{}", prefix);
+		}
+		assert_eq!(
+			list(&fs, "dir"),
+			vec![PathBuf::from("dir/b.txt")],
+			"the prefix dir must not match dir2"
+		);
+		for file_path in [
+			"",
+			"dir",
+			"dir/",
+			"a.txt",
+			"./a.txt",
+			"dir/b.txt",
+			"a.txt/",
+			"dir2",
+		] {
+			let file_path = Path::new(file_path);
+			assert_eq!(
+				fs.contains(file_path),
+				included.contains(file_path),
+				"{}",
+				file_path.display()
+			);
+		}
+		assert_eq!(fs.len(), 3);
+		assert_eq!(included.len(), 3);
+		std::fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn test_set_fs_path() {
+		let path = test_dir("set_fs_path");
+		let mut directory = IncludeDir::Fs(fs_directory(&path.join("moved")));
+		assert!(directory.read(Path::new("a.txt")).is_none());
+		directory.set_fs_path(path.join("root"));
+		assert!(directory.read(Path::new("a.txt")).is_some());
+		std::fs::remove_dir_all(path).unwrap();
 	}
 
-	pub fn hash(&self) -> Option<&'static str> {
-		Some(self.hash)
+	#[cfg(unix)]
+	#[test]
+	fn test_read_symlink() {
+		let path = test_dir("read_symlink");
+		let root = path.join("root");
+		std::os::unix::fs::symlink(
+			path.join("outside").join("secret.txt"),
+			root.join("secret.txt"),
+		)
+		.unwrap();
+		std::os::unix::fs::symlink(path.join("outside"), root.join("outside")).unwrap();
+		std::os::unix::fs::symlink(root.join("a.txt"), root.join("link.txt")).unwrap();
+		let directory = fs_directory(&path);
+		assert!(directory.read(Path::new("secret.txt")).is_none());
+		assert!(directory.read(Path::new("outside/secret.txt")).is_none());
+		// Iterating skips symlinks, so reading does too, even if they point inside the root.
+		assert!(directory.read(Path::new("link.txt")).is_none());
+		let paths = IncludeDir::Fs(directory)
+			.iter()
+			.map(|entry| entry.unwrap().0)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			paths,
+			vec![PathBuf::from("a.txt"), PathBuf::from("dir/b.txt")]
+		);
+		std::fs::remove_dir_all(path).unwrap();
 	}
 }
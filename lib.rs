@@ -1,26 +1,56 @@
 pub use self::builder::{build, BuildOptions};
-use anyhow::Result;
+pub use self::export::{ExportError, ExportErrors, ExportOperation, ExportOptions};
+pub use self::sitemap::{SitemapEntry, SitemapOptions};
+use anyhow::{anyhow, Result};
 use digest::Digest;
 use futures::FutureExt;
-use ignore::Walk;
 use include_dir::IncludeDir;
-use std::{future::Future, path::Path, pin::Pin};
+use once_cell::sync::{Lazy, OnceCell};
+use std::{
+	borrow::Cow,
+	collections::{BTreeMap, HashMap, VecDeque},
+	future::Future,
+	path::Path,
+	pin::Pin,
+	sync::Mutex,
+};
 pub use sunfish_macro::{include_dir, init};
 
 mod builder;
+mod export;
 pub mod include_dir;
+pub mod middleware;
+mod range;
+mod serve;
+mod sitemap;
 pub mod watchserve;
 
 pub enum Route {
 	Static {
-		paths: Option<Box<dyn 'static + Send + Sync + Fn() -> Vec<String>>>,
-		handler: Box<dyn 'static + Send + Sync + Fn(String) -> String>,
+		paths: Option<StaticPaths>,
+		handler: StaticHandler,
+		sitemap: Option<StaticSitemap>,
+		/// The value of the `Cache-Control` header for this route's pages.
+		cache_control: Option<String>,
+		/// Whether to cache the rendered html of this route's pages. See `Route::cached`.
+		cache: bool,
 	},
 	Dynamic {
 		handler: DynamicHandler,
 	},
 }
 
+pub type StaticPaths = Box<dyn 'static + Send + Sync + Fn() -> StaticPathsOutput>;
+
+pub type StaticPathsOutput = Pin<Box<dyn Send + Future<Output = Result<Vec<String>>>>>;
+
+pub type StaticHandler =
+	Box<dyn 'static + Send + Sync + Fn(String, RouteParams) -> StaticHandlerOutput>;
+
+pub type StaticHandlerOutput = Pin<Box<dyn Send + Future<Output = Result<String>>>>;
+
+pub type StaticSitemap = Box<dyn 'static + Send + Sync + Fn(&str) -> Option<SitemapEntry>>;
+
 pub type DynamicHandler = Box<
 	dyn Send + Sync + for<'a> Fn(&'a mut http::Request<hyper::Body>) -> DynamicHandlerOutput<'a>,
 >;
@@ -32,22 +62,101 @@ impl Route {
 	pub fn new_static<H>(handler: H) -> Route
 	where
 		H: 'static + Send + Sync + Fn(String) -> String,
+	{
+		Route::new_static_with_params(move |path, _| handler(path))
+	}
+
+	pub fn new_static_with_paths<P, H>(paths: P, handler: H) -> Route
+	where
+		P: 'static + Send + Sync + Fn() -> Vec<String>,
+		H: 'static + Send + Sync + Fn(String) -> String,
+	{
+		Route::new_static_with_paths_and_params(paths, move |path, _| handler(path))
+	}
+
+	pub fn new_static_with_params<H>(handler: H) -> Route
+	where
+		H: 'static + Send + Sync + Fn(String, RouteParams) -> String,
+	{
+		Route::new_static_async(move |path, params| {
+			let html = handler(path, params);
+			async move { Ok(html) }.boxed()
+		})
+	}
+
+	pub fn new_static_with_paths_and_params<P, H>(paths: P, handler: H) -> Route
+	where
+		P: 'static + Send + Sync + Fn() -> Vec<String>,
+		H: 'static + Send + Sync + Fn(String, RouteParams) -> String,
+	{
+		Route::new_static_with_paths_async(
+			move || {
+				let paths = paths();
+				async move { Ok(paths) }.boxed()
+			},
+			move |path, params| {
+				let html = handler(path, params);
+				async move { Ok(html) }.boxed()
+			},
+		)
+	}
+
+	pub fn new_static_async<H>(handler: H) -> Route
+	where
+		H: 'static + Send + Sync + Fn(String, RouteParams) -> StaticHandlerOutput,
 	{
 		Route::Static {
 			paths: None,
 			handler: Box::new(handler),
+			sitemap: None,
+			cache_control: None,
+			cache: false,
 		}
 	}
 
-	pub fn new_static_with_paths<P, H>(paths: P, handler: H) -> Route
+	pub fn new_static_with_paths_async<P, H>(paths: P, handler: H) -> Route
 	where
-		P: 'static + Send + Sync + Fn() -> Vec<String>,
-		H: 'static + Send + Sync + Fn(String) -> String,
+		P: 'static + Send + Sync + Fn() -> StaticPathsOutput,
+		H: 'static + Send + Sync + Fn(String, RouteParams) -> StaticHandlerOutput,
 	{
 		Route::Static {
 			paths: Some(Box::new(paths)),
 			handler: Box::new(handler),
+			sitemap: None,
+			cache_control: None,
+			cache: false,
+		}
+	}
+
+	/// Set the sitemap entry for each of this static route's pages. Return `None` to leave a page out of the sitemap. This has no effect on dynamic routes, which are never in the sitemap.
+	pub fn with_sitemap<S>(mut self, sitemap: S) -> Route
+	where
+		S: 'static + Send + Sync + Fn(&str) -> Option<SitemapEntry>,
+	{
+		if let Route::Static {
+			sitemap: static_sitemap,
+			..
+		} = &mut self
+		{
+			*static_sitemap = Some(Box::new(sitemap));
 		}
+		self
+	}
+
+	/// Set the `Cache-Control` header for this static route's pages, such as `"public, max-age=60"`. Pages always have an `ETag`, so without this header browsers still revalidate them and receive a 304 response if the page has not changed. This has no effect on dynamic routes, which set their own headers.
+	pub fn with_cache_control(mut self, value: impl Into<String>) -> Route {
+		if let Route::Static { cache_control, .. } = &mut self {
+			*cache_control = Some(value.into());
+		}
+		self
+	}
+
+	/// Render each of this static route's pages only once and serve the rendered html for later requests. A page is cached for each path requested, up to a limit shared by all routes, so this is most useful for routes that match a limited set of paths. This only applies in release builds, so that in debug builds pages reflect changes to any files their handlers read.
+	pub fn cached(mut self) -> Route {
+		if let Route::Static { cache, .. } = &mut self {
+			*cache = true;
+		}
+		self
 	}
 
 	pub fn new_dynamic<H>(handler: H) -> Route
@@ -67,12 +176,66 @@ impl Route {
 		request: &'a mut http::Request<hyper::Body>,
 	) -> DynamicHandlerOutput<'a> {
 		match self {
-			Route::Static { handler, .. } => {
-				let html = handler(request.uri().path().to_owned());
-				async {
-					let response = http::Response::builder()
+			Route::Static {
+				handler,
+				cache_control,
+				cache,
+				..
+			} => {
+				let path = request.uri().path().to_owned();
+				let cache = *cache && cfg!(not(debug_assertions));
+				let prerendered_page = request.extensions().get::<PrerenderedPage>();
+				let cached_page = match prerendered_page {
+					Some(prerendered_page) => Some(prerendered_page.0.clone()),
+					None if cache => RENDERED_PAGES.lock().unwrap().get(&path),
+					None => None,
+				};
+				let page = match cached_page {
+					Some(page) => async move { Ok(page) }.boxed(),
+					None => {
+						let params = route_params(request).cloned().unwrap_or_default();
+						let html = handler(path.clone(), params);
+						async move {
+							let page = RenderedPage::new(html.await?);
+							if cache {
+								RENDERED_PAGES.lock().unwrap().insert(path, page.clone());
+							}
+							Ok::<_, anyhow::Error>(page)
+						}
+						.boxed()
+					}
+				};
+				let cache_control = cache_control.clone();
+				let html_content_type = match request.extensions().get::<HtmlContentType>() {
+					Some(html_content_type) => html_content_type.0.clone(),
+					None => content_type("html").unwrap().to_owned(),
+				};
+				let request: &'a http::Request<hyper::Body> = request;
+				async move {
+					let page = page.await?;
+					let mut response = http::Response::builder()
+						.header(http::header::CONTENT_TYPE, html_content_type)
+						.header(http::header::ETAG, &page.etag);
+					if let Some(content_encoding) = page.content_encoding {
+						response =
+							response.header(http::header::CONTENT_ENCODING, content_encoding);
+					}
+					if page.vary {
+						response = response.header(http::header::VARY, "Accept-Encoding");
+					}
+					if let Some(cache_control) = cache_control {
+						response = response.header(http::header::CACHE_CONTROL, cache_control);
+					}
+					if !is_modified(request.headers(), &page.etag, None) {
+						let response = response
+							.status(http::StatusCode::NOT_MODIFIED)
+							.body(hyper::Body::empty())
+							.unwrap();
+						return Ok(response);
+					}
+					let response = response
 						.status(http::StatusCode::OK)
-						.body(hyper::Body::from(html))
+						.body(hyper::Body::from(page.html))
 						.unwrap();
 					Ok(response)
 				}
@@ -83,29 +246,287 @@ impl Route {
 	}
 }
 
+/// The html of a static route's page and its `ETag`.
+#[derive(Clone)]
+struct RenderedPage {
+	html: hyper::body::Bytes,
+	etag: String,
+	/// The encoding of `html`, if it is a precompressed variant of a prerendered page.
+	content_encoding: Option<&'static str>,
+	/// Whether the page has precompressed variants, so responses vary by `Accept-Encoding`.
+	vary: bool,
+}
+
+impl RenderedPage {
+	fn new(html: String) -> RenderedPage {
+		let etag = format!("\"{}\"", hash(&html));
+		RenderedPage {
+			html: html.into(),
+			etag,
+			content_encoding: None,
+			vary: false,
+		}
+	}
+}
+
+/// The content type of html, including any override in `Sunfish::content_types`. `Sunfish` adds this to the request's extensions so that static routes, which are handled without access to the `Sunfish`, use it.
+struct HtmlContentType(String);
+
+/// A page written by `Sunfish::prerender`. `Sunfish` adds this to the request's extensions when there is one at the request's path, and `Route::handle` serves it instead of calling the route's handler.
+struct PrerenderedPage(RenderedPage);
+
+fn bytes(data: Cow<'static, [u8]>) -> hyper::body::Bytes {
+	match data {
+		Cow::Borrowed(data) => hyper::body::Bytes::from_static(data),
+		Cow::Owned(data) => hyper::body::Bytes::from(data),
+	}
+}
+
+/// The pages of cached static routes, by path. Routes are created for each request, so the cache is shared by the whole process. Requests only reach routes at their canonical paths, because `Sunfish::handle` redirects other paths first.
+static RENDERED_PAGES: Lazy<Mutex<RenderedPages>> = Lazy::new(Default::default);
+
+/// The most pages `RENDERED_PAGES` holds. A cached route with placeholders matches any number of paths, so when the cache is full, the page that was cached first is evicted.
+const RENDERED_PAGES_CAPACITY: usize = 1024;
+
+#[derive(Default)]
+struct RenderedPages {
+	pages: HashMap<String, RenderedPage>,
+	/// The paths of the cached pages, in the order they were cached.
+	paths: VecDeque<String>,
+}
+
+impl RenderedPages {
+	fn get(&self, path: &str) -> Option<RenderedPage> {
+		self.pages.get(path).cloned()
+	}
+
+	fn insert(&mut self, path: String, page: RenderedPage) {
+		if self.pages.insert(path.clone(), page).is_some() {
+			return;
+		}
+		self.paths.push_back(path);
+		if self.paths.len() > RENDERED_PAGES_CAPACITY {
+			let path = self.paths.pop_front().unwrap();
+			self.pages.remove(&path);
+		}
+	}
+}
+
+/// The values of the named placeholder segments in a route's path, such as `id` in `routes/users/_id` or `routes/users/[id]`. The value of a rest segment such as `routes/docs/[...path]` is the remaining path components joined by `/`.
+#[derive(Clone, Debug, Default)]
+pub struct RouteParams(pub BTreeMap<String, String>);
+
+impl RouteParams {
+	/// Match a path against a route's path with placeholders. This is used to get the params for paths that are rendered outside of a request, such as by `Sunfish::export`.
+	pub fn from_path(path_with_placeholders: &str, path: &str) -> RouteParams {
+		let mut params = BTreeMap::new();
+		// Ignore a trailing slash, the same way the generated routes handler does.
+		let mut path_components = path.split('/').collect::<Vec<_>>();
+		if path_components.len() > 2 && path_components.last() == Some(&"") {
+			path_components.pop();
+		}
+		let mut path_components = path_components.into_iter();
+		for placeholder in path_with_placeholders.split('/') {
+			if let Some(name) = rest_placeholder_name(placeholder) {
+				let rest = path_components.by_ref().collect::<Vec<_>>().join("/");
+				params.insert(name.to_owned(), rest);
+				break;
+			}
+			let path_component = match path_components.next() {
+				Some(path_component) => path_component,
+				None => break,
+			};
+			if let Some(name) = placeholder_name(placeholder) {
+				params.insert(name.to_owned(), path_component.to_owned());
+			}
+		}
+		RouteParams(params)
+	}
+
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.0.get(name).map(|value| value.as_str())
+	}
+
+	pub fn parse<T>(&self, name: &str) -> Result<T>
+	where
+		T: std::str::FromStr,
+		T::Err: 'static + std::error::Error + Send + Sync,
+	{
+		let value = self
+			.get(name)
+			.ok_or_else(|| anyhow!("the route has no param named \"{}\"", name))?;
+		let value = value.parse()?;
+		Ok(value)
+	}
+
+	/// Get the path components matched by a rest segment such as `[...path]`.
+	pub fn components(&self, name: &str) -> Option<Vec<&str>> {
+		let value = self.get(name)?;
+		if value.is_empty() {
+			Some(Vec::new())
+		} else {
+			Some(value.split('/').collect())
+		}
+	}
+}
+
+fn placeholder_name(component: &str) -> Option<&str> {
+	if rest_placeholder_name(component).is_some() {
+		None
+	} else if let Some(name) = component
+		.strip_prefix('[')
+		.and_then(|component| component.strip_suffix(']'))
+	{
+		Some(name)
+	} else {
+		component.strip_prefix('_').filter(|name| !name.is_empty())
+	}
+}
+
+fn rest_placeholder_name(component: &str) -> Option<&str> {
+	component
+		.strip_prefix("[[...")
+		.and_then(|component| component.strip_suffix("]]"))
+		.or_else(|| {
+			component
+				.strip_prefix("[...")
+				.and_then(|component| component.strip_suffix(']'))
+		})
+}
+
+pub(crate) fn has_placeholders(path_with_placeholders: &str) -> bool {
+	path_with_placeholders.split('/').any(|component| {
+		component == "_"
+			|| placeholder_name(component).is_some()
+			|| rest_placeholder_name(component).is_some()
+	})
+}
+
+/// Get the params the routes handler matched for this request.
+pub fn route_params(request: &http::Request<hyper::Body>) -> Option<&RouteParams> {
+	request.extensions().get::<RouteParams>()
+}
+
 pub fn path_components(path: &str) -> Vec<&str> {
 	path.split('/').skip(1).collect::<Vec<_>>()
 }
 
+static ASSET_MANIFEST: OnceCell<BTreeMap<String, String>> = OnceCell::new();
+
+/// Load the asset manifest written by `build`. This is called by the code generated by `init!`.
+pub fn load_asset_manifest(asset_manifest: &str) -> Result<()> {
+	let asset_manifest: toml::Value = toml::from_str(asset_manifest)?;
+	let asset_manifest = asset_manifest
+		.as_table()
+		.ok_or_else(|| anyhow!("the asset manifest must be a table"))?
+		.iter()
+		.map(|(path, url)| {
+			let url = url
+				.as_str()
+				.ok_or_else(|| anyhow!("the url for asset \"{}\" must be a string", path))?;
+			Ok((path.to_owned(), url.to_owned()))
+		})
+		.collect::<Result<BTreeMap<_, _>>>()?;
+	// Loading the same manifest again is fine, as happens when `init!` is called more than once.
+	if let Err(asset_manifest) = ASSET_MANIFEST.set(asset_manifest) {
+		if ASSET_MANIFEST.get() != Some(&asset_manifest) {
+			return Err(anyhow!(
+				"a different asset manifest has already been loaded"
+			));
+		}
+	}
+	Ok(())
+}
+
+/// Get the url of the asset at `path`, which is relative to the workspace. This panics if the asset manifest has not been loaded or has no asset at `path`. Use `try_asset_path` where the manifest may not be loaded, such as in client code or tests.
 pub fn asset_path(path: &Path) -> String {
-	let extension = path.extension().map(|e| e.to_str().unwrap()).unwrap();
-	let hash = hash(&path.to_str().unwrap().as_bytes());
-	format!("/assets/{}.{}", hash, extension)
+	if ASSET_MANIFEST.get().is_none() {
+		panic!("the asset manifest has not been loaded, call sunfish::init!() before asset_path");
+	}
+	match try_asset_path(path) {
+		Some(url) => url,
+		None => panic!(
+			"no asset was found at \"{}\", the path must be relative to the workspace and the file must have one of the asset extensions",
+			path.display()
+		),
+	}
 }
 
+/// Get the url of the asset at `path`, or `None` if the asset manifest has not been loaded or has no asset at `path`.
+pub fn try_asset_path(path: &Path) -> Option<String> {
+	ASSET_MANIFEST.get()?.get(path.to_str()?).cloned()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ClientPaths {
 	pub path_js: String,
 	pub path_wasm: String,
 }
 
+static CLIENT_PATHS: OnceCell<BTreeMap<String, ClientPaths>> = OnceCell::new();
+
+/// Load the client paths manifest written by `build`. This is called by the code generated by `init!`.
+pub fn load_client_paths(client_paths: &str) -> Result<()> {
+	let client_paths: toml::Value = toml::from_str(client_paths)?;
+	let client_paths = client_paths
+		.as_table()
+		.ok_or_else(|| anyhow!("the client paths manifest must be a table"))?
+		.iter()
+		.map(|(crate_name, paths)| {
+			let path = |name: &str| {
+				paths
+					.get(name)
+					.and_then(|path| path.as_str())
+					.map(|path| path.to_owned())
+					.ok_or_else(|| {
+						anyhow!(
+							"the {} for client \"{}\" must be a string",
+							name,
+							crate_name
+						)
+					})
+			};
+			let paths = ClientPaths {
+				path_js: path("path_js")?,
+				path_wasm: path("path_wasm")?,
+			};
+			Ok((crate_name.to_owned(), paths))
+		})
+		.collect::<Result<BTreeMap<_, _>>>()?;
+	if let Err(client_paths) = CLIENT_PATHS.set(client_paths) {
+		if CLIENT_PATHS.get() != Some(&client_paths) {
+			return Err(anyhow!(
+				"a different client paths manifest has already been loaded"
+			));
+		}
+	}
+	Ok(())
+}
+
+/// Get the urls of the js and wasm for the client crate named `crate_name`, which are named by the hash of their contents. This panics if the client paths manifest has not been loaded or has no client crate named `crate_name`.
 pub fn client_paths(crate_name: &'static str) -> ClientPaths {
-	let hash = hash(crate_name.as_bytes());
-	ClientPaths {
-		path_js: format!("/js/{}.js", hash),
-		path_wasm: format!("/js/{}_bg.wasm", hash),
+	let client_paths = CLIENT_PATHS.get().unwrap_or_else(|| {
+		panic!("the client paths manifest has not been loaded, call sunfish::init!() before client_paths")
+	});
+	match client_paths.get(crate_name) {
+		Some(client_paths) => client_paths.clone(),
+		None => panic!("no client crate named \"{}\" was built", crate_name),
 	}
 }
 
+/// Whether `path`, relative to the output, is the js or wasm of a client crate.
+fn is_client_path(path: &Path) -> bool {
+	let client_paths = match CLIENT_PATHS.get() {
+		Some(client_paths) => client_paths,
+		None => return false,
+	};
+	client_paths.values().any(|client_paths| {
+		[&client_paths.path_js, &client_paths.path_wasm]
+			.iter()
+			.any(|client_path| Path::new(client_path.trim_start_matches('/')) == path)
+	})
+}
+
 type RoutesHandler = Box<
 	dyn Send + Sync + for<'a> Fn(&'a mut http::Request<hyper::Body>) -> RoutesHandlerOutput<'a>,
 >;
@@ -117,6 +538,64 @@ pub struct Sunfish {
 	pub output: IncludeDir,
 	pub routes_handler: RoutesHandler,
 	pub routes: Vec<RouteInitializer>,
+	/// The Cache-Control header to send with files that are not under /assets.
+	pub cache_control: Option<String>,
+	/// Content types by file extension, which extend and override the built in content types.
+	pub content_types: BTreeMap<String, String>,
+	/// The route in `routes/404`, which is served with status 404 when no route or file matches a request.
+	pub not_found_route: Option<RouteInitializer>,
+	/// The route in `routes/500`, which is served with status 500 when a route returns an error.
+	pub error_route: Option<RouteInitializer>,
+	pub middleware: Vec<middleware::ScopedMiddleware>,
+	pub export_options: ExportOptions,
+	/// If this is set, `export` writes sitemap.xml and `handle` serves it.
+	pub sitemap: Option<SitemapOptions>,
+	/// If this is set, `export` writes robots.txt with these contents and `handle` serves it.
+	pub robots_txt: Option<String>,
+	pub trailing_slash: TrailingSlash,
+	/// A hash of the app's source, which `init!` computes when the app is compiled. `prerender` records it with the pages it renders, and `handle` only serves prerendered pages that were rendered by a build with the same source.
+	pub build_id: String,
+}
+
+/// Whether page paths end with a slash. Routes match paths with or without a trailing slash. If this is `Always` or `Never`, `Sunfish::handle` redirects requests for pages at the other form of a path with status 308, and `Sunfish::export` writes each page where a static host will serve it at its canonical path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrailingSlash {
+	/// Page paths end with a slash, such as `/docs/`, and `/docs` is exported to `docs/index.html`.
+	Always,
+	/// Page paths do not end with a slash, such as `/docs`, and `/docs/` is exported to `docs.html`.
+	Never,
+	/// Pages are served at paths with or without a trailing slash. `/docs` is exported to `docs.html` and `/docs/` is exported to `docs/index.html`.
+	Either,
+}
+
+impl Default for TrailingSlash {
+	fn default() -> TrailingSlash {
+		TrailingSlash::Either
+	}
+}
+
+impl TrailingSlash {
+	/// Get the canonical form of `path`. Repeated leading slashes are collapsed to one, so the canonical path is never a protocol relative url such as `//example.com` when it is used as a redirect's `Location`.
+	pub fn canonical_path(self, path: &str) -> String {
+		let path = format!("/{}", path.trim_start_matches('/'));
+		if path == "/" {
+			return path;
+		}
+		match self {
+			TrailingSlash::Always if !path.ends_with('/') => format!("{}/", path),
+			TrailingSlash::Never => path.trim_end_matches('/').to_owned(),
+			_ => path,
+		}
+	}
+
+	/// Get the path of the file relative to the export directory that a page at `path` is written to.
+	pub fn output_html_path(self, path: &str) -> String {
+		match self.canonical_path(path).as_str() {
+			"/" => "index.html".to_owned(),
+			path if path.ends_with('/') => format!("{}index.html", &path[1..]),
+			path => format!("{}.html", &path[1..]),
+		}
+	}
 }
 
 pub struct RouteInitializer {
@@ -125,60 +604,109 @@ pub struct RouteInitializer {
 }
 
 impl Sunfish {
-	pub fn export(&self, out_dir: &Path, dist_path: &Path) -> Result<()> {
-		let output_path = out_dir.join("output");
-		// Create a new directory at dist_path.
-		if std::fs::metadata(&dist_path).is_ok() {
-			std::fs::remove_dir_all(&dist_path)?;
-		}
-		std::fs::create_dir_all(&dist_path)?;
-		// Copy the contents of the out_dir to the dist_path.
-		for entry in Walk::new(&output_path) {
-			let entry = entry.unwrap();
-			let input_path = entry.path();
-			if !input_path.is_file() {
-				continue;
-			}
-			let path = input_path.strip_prefix(&output_path).unwrap();
-			let output_path = dist_path.join(path);
-			std::fs::create_dir_all(output_path.parent().unwrap()).unwrap();
-			std::fs::copy(&input_path, &output_path).unwrap();
-		}
-		// Render and write the html for each page.
-		for route in self.routes.iter() {
-			match (route.init)() {
-				Route::Static { paths, handler } => {
-					let paths = paths
-						.map(|paths| paths())
-						.unwrap_or_else(|| vec![route.path_with_placeholders.clone()]);
-					for path in paths {
-						let output_html_path = match path.as_str() {
-							"/" => "/index.html".to_owned(),
-							path if path.ends_with('/') => format!("{}index.html", path),
-							path => format!("{}.html", path),
-						};
-						let output_html_path =
-							dist_path.join(&output_html_path.strip_prefix('/').unwrap());
-						let html = handler(path);
-						std::fs::create_dir_all(output_html_path.parent().unwrap()).unwrap();
-						std::fs::write(&output_html_path, html)?;
-					}
-				}
-				Route::Dynamic { .. } => continue,
-			}
-		}
-		Ok(())
+	pub async fn handle(
+		&self,
+		request: &mut http::Request<hyper::Body>,
+	) -> Result<Option<http::Response<hyper::Body>>> {
+		let next = middleware::Next {
+			sunfish: self,
+			middleware: &self.middleware,
+		};
+		next.run(request).await
 	}
 
-	pub async fn handle(
+	/// Add a middleware that runs for requests whose path is `path_prefix` or is under it. Pass `/` to run it for every request. Middleware runs in the order it was added.
+	pub fn add_middleware<M>(&mut self, path_prefix: &str, middleware: M)
+	where
+		M: 'static + middleware::Middleware,
+	{
+		self.middleware.push(middleware::ScopedMiddleware {
+			path_prefix: path_prefix.to_owned(),
+			middleware: Box::new(middleware),
+		});
+	}
+
+	async fn handle_without_middleware(
 		&self,
 		request: &mut http::Request<hyper::Body>,
 	) -> Result<Option<http::Response<hyper::Body>>> {
-		let response = self.serve_page(request).await?;
+		let html_content_type = self.content_type(Path::new("index.html")).unwrap();
+		let html_content_type = HtmlContentType(html_content_type.to_owned());
+		request.extensions_mut().insert(html_content_type);
+		let path = request.uri().path();
+		let canonical_path = self.trailing_slash.canonical_path(path);
+		if canonical_path != path {
+			// Files are served at their exact paths, so only redirect if there is no file at this path.
+			if let Some(response) = self.serve_asset(request).await? {
+				return Ok(Some(response));
+			}
+			if let Some(response) = self.serve_sitemap_or_robots_txt(request).await? {
+				return Ok(Some(response));
+			}
+			let location = match request.uri().query() {
+				Some(query) => format!("{}?{}", canonical_path, query),
+				None => canonical_path,
+			};
+			let response = http::Response::builder()
+				.status(http::StatusCode::PERMANENT_REDIRECT)
+				.header(http::header::LOCATION, location)
+				.body(hyper::Body::empty())
+				.unwrap();
+			return Ok(Some(response));
+		}
+		let response = match self.serve_page(request).await {
+			Ok(Some(response)) => Ok(Some(response)),
+			Ok(None) => self.serve_asset(request).await,
+			Err(error) => Err(error),
+		};
 		let response = match response {
-			Some(response) => Some(response),
-			None => self.serve_asset(request).await?,
+			Ok(None) => self.serve_sitemap_or_robots_txt(request).await,
+			response => response,
 		};
+		match response {
+			Ok(Some(response)) => Ok(Some(response)),
+			Ok(None) => match self.not_found_route.as_ref() {
+				Some(not_found_route) => {
+					let response = self
+						.serve_error_route(not_found_route, http::StatusCode::NOT_FOUND, request)
+						.await?;
+					Ok(Some(response))
+				}
+				None => Ok(None),
+			},
+			Err(error) => match self.error_route.as_ref() {
+				Some(error_route) => {
+					tracing::error!("{:?}", error);
+					let response = self
+						.serve_error_route(
+							error_route,
+							http::StatusCode::INTERNAL_SERVER_ERROR,
+							request,
+						)
+						.await?;
+					Ok(Some(response))
+				}
+				None => Err(error),
+			},
+		}
+	}
+
+	async fn serve_error_route(
+		&self,
+		route: &RouteInitializer,
+		status: http::StatusCode,
+		request: &mut http::Request<hyper::Body>,
+	) -> Result<http::Response<hyper::Body>> {
+		let mut route = (route.init)();
+		// Error pages are served at any path, so caching them by path would grow the cache without bound.
+		if let Route::Static { cache, .. } = &mut route {
+			*cache = false;
+		}
+		// The status is replaced below, so the response must always have a body.
+		request.headers_mut().remove(http::header::IF_NONE_MATCH);
+		request.extensions_mut().remove::<PrerenderedPage>();
+		let mut response = route.handle(request).await?;
+		*response.status_mut() = status;
 		Ok(response)
 	}
 
@@ -186,9 +714,61 @@ impl Sunfish {
 		&self,
 		request: &mut http::Request<hyper::Body>,
 	) -> Result<Option<http::Response<hyper::Body>>> {
+		// If the page was written by `prerender`, pass it to the route to serve instead of calling its handler.
+		let path = Path::new(builder::PRERENDERED_DIR)
+			.join(self.trailing_slash.output_html_path(request.uri().path()));
+		if let Some(page) = self.read_prerendered_page(request, &path) {
+			request.extensions_mut().insert(page);
+		}
 		self.routes_handler.as_ref()(request).await
 	}
 
+	/// Read the prerendered page at `path` in `output`, choosing the precompressed variant the client prefers.
+	fn read_prerendered_page(
+		&self,
+		request: &http::Request<hyper::Body>,
+		path: &Path,
+	) -> Option<PrerenderedPage> {
+		let build_id_path =
+			Path::new(builder::PRERENDERED_DIR).join(builder::PRERENDERED_BUILD_ID_PATH);
+		let build_id = self.output.read(&build_id_path)?.data().ok()?;
+		if build_id.as_ref() != self.build_id.as_bytes() {
+			static WARN_STALE_PRERENDERED_PAGES: std::sync::Once = std::sync::Once::new();
+			WARN_STALE_PRERENDERED_PAGES.call_once(|| {
+				tracing::warn!("the prerendered pages are from a different build of the app and will not be served, run prerender again to update them");
+			});
+			return None;
+		}
+		let file = self.output.read(path)?;
+		let hash = file.hash().ok()?;
+		let accept_encoding = request.headers().get(http::header::ACCEPT_ENCODING);
+		let mut has_variants = false;
+		let mut variant = None;
+		for (encoding, extension) in PRECOMPRESSED_ENCODINGS {
+			if let Some(variant_file) = self.output.read(&builder::variant_path(path, extension)) {
+				has_variants = true;
+				if variant.is_none() && accepts_encoding(accept_encoding, encoding) {
+					variant = Some((encoding, variant_file));
+				}
+			}
+		}
+		let page = match variant {
+			Some((encoding, variant_file)) => RenderedPage {
+				html: bytes(variant_file.data().ok()?),
+				etag: format!("\"{}-{}\"", hash, encoding),
+				content_encoding: Some(encoding),
+				vary: true,
+			},
+			None => RenderedPage {
+				html: bytes(file.data().ok()?),
+				etag: format!("\"{}\"", hash),
+				content_encoding: None,
+				vary: has_variants,
+			},
+		};
+		Some(PrerenderedPage(page))
+	}
+
 	async fn serve_asset(
 		&self,
 		request: &http::Request<hyper::Body>,
@@ -197,55 +777,566 @@ impl Sunfish {
 		let uri = request.uri().clone();
 		let path_and_query = uri.path_and_query().unwrap();
 		let path = path_and_query.path();
-		if method != ::http::Method::GET {
+		let head = method == ::http::Method::HEAD;
+		if method != ::http::Method::GET && !head {
 			return Ok(None);
 		}
 		let path = Path::new(path.strip_prefix('/').unwrap());
+		// Prerendered pages are only served at the paths of their routes.
+		if path.starts_with(builder::PRERENDERED_DIR) {
+			return Ok(None);
+		}
 		let file = if let Some(file) = self.output.read(path) {
 			file
 		} else {
 			return Ok(None);
 		};
+		// A file read from the filesystem may be removed after it is found, such as while the app is being rebuilt, in which case it is not found.
+		let modified = match file.modified() {
+			Ok(modified) => modified,
+			Err(_) => return Ok(None),
+		};
+		let content_type = self.content_type(path);
 		let mut response = http::Response::builder();
-		if let Some(content_type) = content_type(path) {
+		if let Some(content_type) = content_type {
 			response = response.header(http::header::CONTENT_TYPE, content_type);
 		}
-		if let Some(hash) = file.hash() {
-			response = response.header(http::header::ETAG, hash);
-			if let Some(etag) = request.headers().get(http::header::IF_NONE_MATCH) {
-				if etag.as_bytes() == hash.as_bytes() {
-					response = response.status(http::StatusCode::NOT_MODIFIED);
-					let response = response.body(hyper::Body::empty()).unwrap();
-					return Ok(Some(response));
+		// Assets and client js and wasm are named by the hash of their contents, so they never change.
+		if path.starts_with("assets") || is_client_path(path) {
+			response = response.header(http::header::CACHE_CONTROL, IMMUTABLE_CACHE_CONTROL);
+		} else if let Some(cache_control) = self.cache_control.as_ref() {
+			response = response.header(http::header::CACHE_CONTROL, cache_control);
+		}
+		response = response.header(http::header::ACCEPT_RANGES, "bytes");
+		// Serve a precompressed variant of the file if the client accepts it. Range requests are always served from the uncompressed file, because the ranges would otherwise refer to the compressed bytes.
+		let headers = request.headers();
+		let range = headers
+			.get(http::header::RANGE)
+			.and_then(|range| range.to_str().ok());
+		let accept_encoding = headers.get(http::header::ACCEPT_ENCODING);
+		let mut has_variants = false;
+		let mut variant = None;
+		for (encoding, extension) in PRECOMPRESSED_ENCODINGS {
+			if let Some(variant_file) = self.output.read(&builder::variant_path(path, extension)) {
+				has_variants = true;
+				if variant.is_none()
+					&& range.is_none()
+					&& accepts_encoding(accept_encoding, encoding)
+				{
+					variant = Some((encoding, variant_file));
 				}
 			}
 		}
-		response = response.status(http::StatusCode::OK);
-		let response = response.body(hyper::Body::from(file.data())).unwrap();
+		// An included file that is stored compressed can be sent without decompressing it if the client accepts its compression.
+		let included_compressed_data = match &file {
+			include_dir::FsOrIncludedFile::Included(file) => file.compressed_data(),
+			include_dir::FsOrIncludedFile::Fs(_) => None,
+		};
+		if has_variants || included_compressed_data.is_some() {
+			response = response.header(http::header::VARY, "Accept-Encoding");
+		}
+		let mut compressed_data = None;
+		let file = match variant {
+			Some((encoding, variant_file)) => {
+				response = response.header(http::header::CONTENT_ENCODING, encoding);
+				variant_file
+			}
+			None => {
+				if let Some((compression, data)) = included_compressed_data {
+					let encoding = compression.content_encoding();
+					if range.is_none() && accepts_encoding(accept_encoding, encoding) {
+						response = response.header(http::header::CONTENT_ENCODING, encoding);
+						compressed_data = Some((encoding, data));
+					}
+				}
+				file
+			}
+		};
+		let (hash, len) = match (file.hash(), file.len()) {
+			(Ok(hash), Ok(len)) => (hash, len),
+			_ => return Ok(None),
+		};
+		let etag = match compressed_data {
+			Some((encoding, _)) => format!("\"{}-{}\"", hash, encoding),
+			None => format!("\"{}\"", hash),
+		};
+		response = response.header(http::header::ETAG, &etag);
+		let last_modified = modified.map(httpdate::HttpDate::from);
+		if let Some(last_modified) = last_modified {
+			response = response.header(http::header::LAST_MODIFIED, last_modified.to_string());
+		}
+		if !is_modified(headers, &etag, last_modified) {
+			response = response.status(http::StatusCode::NOT_MODIFIED);
+			let response = response.body(hyper::Body::empty()).unwrap();
+			return Ok(Some(response));
+		}
+		let ranges = match range {
+			Some(range) if if_range_matches(headers, &etag, last_modified) => {
+				range::parse_range(range, len)
+			}
+			_ => None,
+		};
+		let ranges = match ranges {
+			Some(range::Ranges::Satisfiable(ranges)) => ranges,
+			Some(range::Ranges::Unsatisfiable) => {
+				response = response
+					.status(http::StatusCode::RANGE_NOT_SATISFIABLE)
+					.header(http::header::CONTENT_RANGE, format!("bytes */{}", len));
+				let response = response.body(hyper::Body::empty()).unwrap();
+				return Ok(Some(response));
+			}
+			None => {
+				response = response.status(http::StatusCode::OK);
+				let body = if let Some((_, data)) = compressed_data {
+					response = response.header(http::header::CONTENT_LENGTH, data.len());
+					if head {
+						hyper::Body::empty()
+					} else {
+						hyper::Body::from(data)
+					}
+				} else {
+					response = response.header(http::header::CONTENT_LENGTH, len);
+					if head {
+						hyper::Body::empty()
+					} else {
+						hyper::Body::wrap_stream(file.stream())
+					}
+				};
+				let response = response.body(body).unwrap();
+				return Ok(Some(response));
+			}
+		};
+		response = response.status(http::StatusCode::PARTIAL_CONTENT);
+		let body = if let [range] = ranges.as_slice() {
+			response = response
+				.header(
+					http::header::CONTENT_RANGE,
+					range::content_range(range, len),
+				)
+				.header(http::header::CONTENT_LENGTH, range.end - range.start);
+			if head {
+				hyper::Body::empty()
+			} else {
+				hyper::Body::wrap_stream(file.stream_range(range.clone()))
+			}
+		} else {
+			let multipart = range::Multipart::new(&ranges, len, content_type);
+			// Each part has the file's content type, and the response has the multipart content type.
+			let headers = response.headers_mut().unwrap();
+			headers.insert(
+				http::header::CONTENT_TYPE,
+				multipart.content_type().parse().unwrap(),
+			);
+			headers.insert(
+				http::header::CONTENT_LENGTH,
+				multipart.content_length().into(),
+			);
+			if head {
+				hyper::Body::empty()
+			} else {
+				hyper::Body::wrap_stream(multipart.into_stream(&file))
+			}
+		};
+		let response = response.body(body).unwrap();
 		Ok(Some(response))
 	}
+
+	/// Whether the route at `route_path` is the not found or error route.
+	pub(crate) fn is_error_route(&self, route_path: &str) -> bool {
+		[self.not_found_route.as_ref(), self.error_route.as_ref()]
+			.iter()
+			.flatten()
+			.any(|route| route.path_with_placeholders == route_path)
+	}
+
+	pub fn content_type(&self, path: &Path) -> Option<&str> {
+		let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+		match self.content_types.get(&extension) {
+			Some(content_type) => Some(content_type),
+			None => content_type(&extension),
+		}
+	}
 }
 
-fn content_type(path: &std::path::Path) -> Option<&'static str> {
-	let path = path.to_str().unwrap();
-	if path.ends_with(".css") {
-		Some("text/css")
-	} else if path.ends_with(".js") {
-		Some("text/javascript")
-	} else if path.ends_with(".svg") {
-		Some("image/svg+xml")
-	} else if path.ends_with(".wasm") {
-		Some("application/wasm")
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// The encodings `build` precompresses files with and the extensions of the variants it writes, in order of preference.
+const PRECOMPRESSED_ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Evaluate `If-None-Match`, or `If-Modified-Since` if there is no `If-None-Match`, and return false if the client's copy of the file is current.
+fn is_modified(
+	headers: &http::HeaderMap,
+	etag: &str,
+	last_modified: Option<httpdate::HttpDate>,
+) -> bool {
+	if let Some(if_none_match) = headers.get(http::header::IF_NONE_MATCH) {
+		let if_none_match = match if_none_match.to_str() {
+			Ok(if_none_match) => if_none_match,
+			Err(_) => return true,
+		};
+		// If-None-Match uses the weak comparison, so a weak validator matches a strong ETag with the same value.
+		return !if_none_match.split(',').map(str::trim).any(|candidate| {
+			candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+		});
+	}
+	let if_modified_since = headers
+		.get(http::header::IF_MODIFIED_SINCE)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.parse::<httpdate::HttpDate>().ok());
+	match (if_modified_since, last_modified) {
+		(Some(if_modified_since), Some(last_modified)) => last_modified > if_modified_since,
+		_ => true,
+	}
+}
+
+/// Return true if the `Range` header should be honored, which is when there is no `If-Range` header or its validator matches the file. `If-Range` uses the strong comparison, so a weak ETag never matches.
+fn if_range_matches(
+	headers: &http::HeaderMap,
+	etag: &str,
+	last_modified: Option<httpdate::HttpDate>,
+) -> bool {
+	let if_range = match headers.get(http::header::IF_RANGE) {
+		Some(if_range) => if_range,
+		None => return true,
+	};
+	let if_range = match if_range.to_str() {
+		Ok(if_range) => if_range.trim(),
+		Err(_) => return false,
+	};
+	if if_range.starts_with('"') || if_range.starts_with("W/") {
+		if_range == etag
 	} else {
-		None
+		match (if_range.parse::<httpdate::HttpDate>(), last_modified) {
+			(Ok(date), Some(last_modified)) => date == last_modified,
+			_ => false,
+		}
+	}
+}
+
+fn accepts_encoding(accept_encoding: Option<&http::HeaderValue>, encoding: &str) -> bool {
+	let accept_encoding = match accept_encoding.and_then(|value| value.to_str().ok()) {
+		Some(accept_encoding) => accept_encoding,
+		None => return false,
+	};
+	let mut wildcard = None;
+	for item in accept_encoding.split(',') {
+		let mut params = item.split(';');
+		let name = params.next().unwrap().trim();
+		let quality = params
+			.find_map(|param| param.trim().strip_prefix("q="))
+			.and_then(|quality| quality.trim().parse::<f32>().ok())
+			.unwrap_or(1.0);
+		if name.eq_ignore_ascii_case(encoding) {
+			return quality > 0.0;
+		} else if name == "*" {
+			wildcard = Some(quality > 0.0);
+		}
 	}
+	wildcard.unwrap_or(false)
+}
+
+fn content_type(extension: &str) -> Option<&'static str> {
+	let content_type = match extension {
+		"avif" => "image/avif",
+		"css" => "text/css; charset=utf-8",
+		"csv" => "text/csv; charset=utf-8",
+		"gif" => "image/gif",
+		"htm" | "html" => "text/html; charset=utf-8",
+		"ico" => "image/x-icon",
+		"jpeg" | "jpg" => "image/jpeg",
+		"js" | "mjs" => "text/javascript; charset=utf-8",
+		"json" | "map" => "application/json",
+		"md" => "text/markdown; charset=utf-8",
+		"mp3" => "audio/mpeg",
+		"mp4" => "video/mp4",
+		"otf" => "font/otf",
+		"pdf" => "application/pdf",
+		"png" => "image/png",
+		"svg" => "image/svg+xml",
+		"ttf" => "font/ttf",
+		"txt" => "text/plain; charset=utf-8",
+		"wasm" => "application/wasm",
+		"webm" => "video/webm",
+		"webmanifest" => "application/manifest+json",
+		"webp" => "image/webp",
+		"woff" => "font/woff",
+		"woff2" => "font/woff2",
+		"xml" => "text/xml; charset=utf-8",
+		"zip" => "application/zip",
+		_ => return None,
+	};
+	Some(content_type)
 }
 
 pub fn hash(bytes: impl AsRef<[u8]>) -> String {
 	let mut hash: sha2::Sha256 = Digest::new();
 	hash.update(bytes);
+	finish_hash(hash)
+}
+
+/// Hash the data read from `reader` without reading it all into memory. This is the same as the `hash` of the data.
+pub(crate) fn hash_reader(mut reader: impl std::io::Read) -> std::io::Result<String> {
+	let mut hash: sha2::Sha256 = Digest::new();
+	std::io::copy(&mut reader, &mut hash)?;
+	Ok(finish_hash(hash))
+}
+
+fn finish_hash(hash: sha2::Sha256) -> String {
 	let hash = hash.finalize();
 	let hash = hex::encode(hash);
 	let hash = &hash[0..16];
 	hash.to_owned()
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn sunfish(trailing_slash: TrailingSlash) -> Sunfish {
+		Sunfish {
+			output: IncludeDir::Included(include_dir::IncludedDirectory(BTreeMap::new())),
+			routes_handler: Box::new(|_| async { Ok(None) }.boxed()),
+			routes: Vec::new(),
+			cache_control: None,
+			content_types: BTreeMap::new(),
+			not_found_route: None,
+			error_route: None,
+			middleware: Vec::new(),
+			export_options: Default::default(),
+			sitemap: None,
+			robots_txt: None,
+			trailing_slash,
+			build_id: String::new(),
+		}
+	}
+
+	fn location(trailing_slash: TrailingSlash, uri: &str) -> Option<String> {
+		let sunfish = sunfish(trailing_slash);
+		let mut request = http::Request::builder()
+			.uri(uri)
+			.body(hyper::Body::empty())
+			.unwrap();
+		let response = futures::executor::block_on(sunfish.handle(&mut request)).unwrap()?;
+		assert_eq!(response.status(), http::StatusCode::PERMANENT_REDIRECT);
+		let location = response.headers().get(http::header::LOCATION).unwrap();
+		Some(location.to_str().unwrap().to_owned())
+	}
+
+	#[test]
+	fn test_redirect_never_collapses_leading_slashes() {
+		assert_eq!(
+			location(TrailingSlash::Never, "//evil.com/").as_deref(),
+			Some("/evil.com")
+		);
+	}
+
+	#[test]
+	fn test_redirect_always_collapses_leading_slashes() {
+		assert_eq!(
+			location(TrailingSlash::Always, "//evil.com").as_deref(),
+			Some("/evil.com/")
+		);
+	}
+
+	#[test]
+	fn test_redirect_never_root() {
+		assert_eq!(location(TrailingSlash::Never, "//").as_deref(), Some("/"));
+	}
+
+	#[test]
+	fn test_from_path_ignores_trailing_slash() {
+		let params = RouteParams::from_path("/docs/[...path]", "/docs/a/b/");
+		assert_eq!(params.get("path"), Some("a/b"));
+		let params = RouteParams::from_path("/users/[id]", "/users/1/");
+		assert_eq!(params.get("id"), Some("1"));
+	}
+
+	fn included_file(data: &'static [u8]) -> include_dir::IncludedFile {
+		include_dir::IncludedFile {
+			data: include_dir::IncludedData::Uncompressed(data),
+			hash: "0123456789abcdef",
+			modified: None,
+		}
+	}
+
+	#[test]
+	fn test_client_paths_are_immutable() {
+		load_client_paths(
+			"[app_client]\npath_js = \"/js/0123.js\"\npath_wasm = \"/js/4567.wasm\"\n",
+		)
+		.unwrap();
+		assert_eq!(client_paths("app_client").path_wasm, "/js/4567.wasm");
+		let mut sunfish = sunfish(TrailingSlash::Either);
+		let files = ["js/0123.js", "js/4567.wasm", "js/snippets/inline0.js"]
+			.iter()
+			.map(|path| (Path::new(path), included_file(b"data")))
+			.collect();
+		sunfish.output = IncludeDir::Included(include_dir::IncludedDirectory(files));
+		let cache_control = |uri: &str| {
+			let mut request = http::Request::builder()
+				.uri(uri)
+				.body(hyper::Body::empty())
+				.unwrap();
+			let response = futures::executor::block_on(sunfish.handle(&mut request))
+				.unwrap()
+				.unwrap();
+			response
+				.headers()
+				.get(http::header::CACHE_CONTROL)
+				.map(|value| value.to_str().unwrap().to_owned())
+		};
+		assert_eq!(
+			cache_control("/js/0123.js").as_deref(),
+			Some(IMMUTABLE_CACHE_CONTROL)
+		);
+		assert_eq!(
+			cache_control("/js/4567.wasm").as_deref(),
+			Some(IMMUTABLE_CACHE_CONTROL)
+		);
+		assert_eq!(cache_control("/js/snippets/inline0.js"), None);
+	}
+
+	fn accepts(accept_encoding: Option<&str>, encoding: &str) -> bool {
+		let accept_encoding = accept_encoding.map(http::HeaderValue::from_str);
+		let accept_encoding = accept_encoding.map(Result::unwrap);
+		accepts_encoding(accept_encoding.as_ref(), encoding)
+	}
+
+	#[test]
+	fn test_accepts_encoding() {
+		assert!(accepts(Some("gzip, br"), "br"));
+		assert!(accepts(Some("gzip;q=0.5, br;q=1.0"), "gzip"));
+		assert!(!accepts(Some("gzip"), "br"));
+		assert!(!accepts(Some(""), "br"));
+	}
+
+	#[test]
+	fn test_accepts_encoding_quality_zero() {
+		assert!(!accepts(Some("br;q=0, gzip"), "br"));
+		assert!(!accepts(Some("br; q=0.0"), "br"));
+		assert!(accepts(Some("br;q=0.001"), "br"));
+	}
+
+	#[test]
+	fn test_accepts_encoding_wildcard() {
+		assert!(accepts(Some("*"), "br"));
+		assert!(!accepts(Some("*;q=0"), "br"));
+		assert!(!accepts(Some("*, br;q=0"), "br"));
+		assert!(accepts(Some("*;q=0, br"), "br"));
+	}
+
+	#[test]
+	fn test_accepts_encoding_is_case_insensitive() {
+		assert!(accepts(Some("BR, GZip"), "br"));
+		assert!(accepts(Some("BR, GZip"), "gzip"));
+	}
+
+	#[test]
+	fn test_accepts_encoding_missing_header() {
+		assert!(!accepts(None, "br"));
+		assert!(!accepts(None, "gzip"));
+	}
+
+	fn headers(headers: &[(http::header::HeaderName, &str)]) -> http::HeaderMap {
+		headers
+			.iter()
+			.map(|(name, value)| (name.clone(), http::HeaderValue::from_str(value).unwrap()))
+			.collect()
+	}
+
+	#[test]
+	fn test_is_modified_if_none_match() {
+		let etag = "\"abc\"";
+		let is_modified = |if_none_match: &str| {
+			is_modified(
+				&headers(&[(http::header::IF_NONE_MATCH, if_none_match)]),
+				etag,
+				None,
+			)
+		};
+		assert!(!is_modified("\"abc\""));
+		assert!(!is_modified("\"xyz\", \"abc\""));
+		assert!(!is_modified("W/\"abc\""));
+		assert!(!is_modified("*"));
+		assert!(is_modified("\"xyz\", W/\"def\""));
+		assert!(super::is_modified(&headers(&[]), etag, None));
+	}
+
+	#[test]
+	fn test_is_modified_if_modified_since() {
+		let last_modified = httpdate::HttpDate::from(
+			std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000),
+		);
+		let is_modified = |headers: &[(http::header::HeaderName, &str)]| {
+			super::is_modified(&self::headers(headers), "\"abc\"", Some(last_modified))
+		};
+		let date = last_modified.to_string();
+		assert!(!is_modified(&[(http::header::IF_MODIFIED_SINCE, &date)]));
+		assert!(is_modified(&[(
+			http::header::IF_MODIFIED_SINCE,
+			"Thu, 01 Jan 1970 00:00:00 GMT"
+		)]));
+		// If-None-Match takes precedence over If-Modified-Since.
+		assert!(is_modified(&[
+			(http::header::IF_NONE_MATCH, "\"xyz\""),
+			(http::header::IF_MODIFIED_SINCE, &date),
+		]));
+	}
+
+	#[test]
+	fn test_if_range_matches() {
+		let last_modified = httpdate::HttpDate::from(
+			std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000),
+		);
+		let if_range_matches = |if_range: &str| {
+			super::if_range_matches(
+				&headers(&[(http::header::IF_RANGE, if_range)]),
+				"\"abc\"",
+				Some(last_modified),
+			)
+		};
+		assert!(super::if_range_matches(&headers(&[]), "\"abc\"", None));
+		assert!(if_range_matches("\"abc\""));
+		assert!(!if_range_matches("\"xyz\""));
+		// If-Range uses the strong comparison, so a weak validator never matches.
+		assert!(!if_range_matches("W/\"abc\""));
+		assert!(if_range_matches(&last_modified.to_string()));
+		assert!(!if_range_matches("Thu, 01 Jan 1970 00:00:00 GMT"));
+	}
+
+	#[test]
+	fn test_range_of_empty_file_is_not_satisfiable() {
+		let mut sunfish = sunfish(TrailingSlash::Either);
+		let files = vec![(Path::new("empty.txt"), included_file(b""))];
+		sunfish.output =
+			IncludeDir::Included(include_dir::IncludedDirectory(files.into_iter().collect()));
+		let mut request = http::Request::builder()
+			.uri("/empty.txt")
+			.header(http::header::RANGE, "bytes=0-")
+			.body(hyper::Body::empty())
+			.unwrap();
+		let response = futures::executor::block_on(sunfish.handle(&mut request))
+			.unwrap()
+			.unwrap();
+		assert_eq!(response.status(), http::StatusCode::RANGE_NOT_SATISFIABLE);
+		assert_eq!(
+			response.headers().get(http::header::CONTENT_RANGE).unwrap(),
+			"bytes */0"
+		);
+	}
+
+	#[test]
+	fn test_rendered_pages_evicts_oldest() {
+		let mut rendered_pages = RenderedPages::default();
+		for index in 0..=RENDERED_PAGES_CAPACITY {
+			let path = format!("/blog/{}", index);
+			rendered_pages.insert(path.clone(), RenderedPage::new(path));
+		}
+		assert_eq!(rendered_pages.pages.len(), RENDERED_PAGES_CAPACITY);
+		assert!(rendered_pages.get("/blog/0").is_none());
+		assert!(rendered_pages.get("/blog/1").is_some());
+		assert!(rendered_pages
+			.get(&format!("/blog/{}", RENDERED_PAGES_CAPACITY))
+			.is_some());
+	}
+}
@@ -1,68 +1,337 @@
 use digest::Digest;
 use quote::quote;
-use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::{
+	io::Write,
+	path::{Path, PathBuf},
+};
 
 pub fn include_dir(input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::TokenStream> {
-	let path: syn::LitStr = syn::parse2(input)?;
-	let path = Path::new(&path.value()).canonicalize().unwrap();
+	let args: Args = syn::parse2(input)?;
+	let path = Path::new(&args.path.value()).canonicalize().unwrap();
 	let path_string = path.display().to_string();
+	// Build the overrides even if the files are read from the filesystem, so invalid globs are a compile error rather than a panic when the directory is read.
+	let overrides = overrides(&path, &args)?;
+	let include = args.include.iter().map(|glob| glob.value());
+	let exclude = args.exclude.iter().map(|glob| glob.value());
 	let fs_directory = quote! {{
-	  let path = std::path::PathBuf::from(#path_string);
-		let fs_directory = sunfish::include_dir::FsDirectory(path);
+		let fs_directory = sunfish::include_dir::FsDirectory {
+			path: std::path::PathBuf::from(#path_string),
+			include: vec![#(#include.to_owned()),*],
+			exclude: vec![#(#exclude.to_owned()),*],
+		};
 		sunfish::include_dir::IncludeDir::Fs(fs_directory)
 	}};
-	let embedded_directory = embedded_directory(&path);
-	let embedded_directory = quote! {{
-		sunfish::include_dir::IncludeDir::Included(#embedded_directory)
-	}};
-	let code = quote! {{
-	  #[cfg(debug_assertions)]
-	  #fs_directory
-	  #[cfg(not(debug_assertions))]
-	  #embedded_directory
-	}};
+	let embedded_directory = || -> syn::Result<proc_macro2::TokenStream> {
+		let embedded_directory = embedded_directory(&path, &args, overrides)?;
+		Ok(quote! {{
+			sunfish::include_dir::IncludeDir::Included(#embedded_directory)
+		}})
+	};
+	let code = match args.embed {
+		Embed::Always => embedded_directory()?,
+		Embed::Never => fs_directory,
+		Embed::Auto => {
+			// Expand the embedded directory in a separate invocation, so it is only expanded, and its files only read and compressed, in release builds.
+			let include = args.include.iter();
+			let exclude = args.exclude.iter();
+			let compression = match args.compression {
+				None => "none",
+				Some(Compression::Gzip) => "gzip",
+				Some(Compression::Brotli) => "brotli",
+			};
+			quote! {{
+				#[cfg(debug_assertions)]
+				#fs_directory
+				#[cfg(not(debug_assertions))]
+				sunfish::include_dir!(
+					#path_string,
+					include = [#(#include),*],
+					exclude = [#(#exclude),*],
+					embed = "always",
+					compression = #compression,
+				)
+			}}
+		}
+	};
 	Ok(code)
 }
 
-fn embedded_directory(path: &Path) -> proc_macro2::TokenStream {
-	let mut absolute_paths: Vec<PathBuf> = WalkDir::new(&path)
-		.into_iter()
-		.filter_map(|entry| {
-			let entry = entry.unwrap();
-			let path = entry.path().to_owned();
-			let metadata = std::fs::metadata(&path).unwrap();
-			if metadata.is_file() {
-				Some(path)
-			} else {
-				None
+struct Args {
+	path: syn::LitStr,
+	include: Vec<syn::LitStr>,
+	exclude: Vec<syn::LitStr>,
+	embed: Embed,
+	compression: Option<Compression>,
+}
+
+/// Whether to embed the files in the binary or read them from the filesystem. `Auto` reads them from the filesystem in debug builds and embeds them in release builds.
+enum Embed {
+	Always,
+	Never,
+	Auto,
+}
+
+#[derive(Clone, Copy)]
+enum Compression {
+	Gzip,
+	Brotli,
+}
+
+impl syn::parse::Parse for Args {
+	fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+		let path = input.parse()?;
+		let mut include = Vec::new();
+		let mut exclude = Vec::new();
+		let mut embed = Embed::Auto;
+		let mut compression = None;
+		while !input.is_empty() {
+			input.parse::<syn::Token![,]>()?;
+			if input.is_empty() {
+				break;
+			}
+			let key: syn::Ident = input.parse()?;
+			input.parse::<syn::Token![=]>()?;
+			match key.to_string().as_str() {
+				"include" => include = parse_globs(input)?,
+				"exclude" => exclude = parse_globs(input)?,
+				"embed" => {
+					let value: syn::LitStr = input.parse()?;
+					embed = match value.value().as_str() {
+						"always" => Embed::Always,
+						"never" => Embed::Never,
+						"auto" => Embed::Auto,
+						_ => {
+							return Err(syn::Error::new(
+								value.span(),
+								"embed must be \"always\", \"never\", or \"auto\"",
+							))
+						}
+					};
+				}
+				"compression" => {
+					let value: syn::LitStr = input.parse()?;
+					compression = match value.value().as_str() {
+						"none" => None,
+						"gzip" => Some(Compression::Gzip),
+						"br" | "brotli" => Some(Compression::Brotli),
+						_ => {
+							return Err(syn::Error::new(
+								value.span(),
+								"compression must be \"none\", \"gzip\", or \"brotli\"",
+							))
+						}
+					};
+				}
+				_ => {
+					return Err(syn::Error::new(
+						key.span(),
+						format!("unknown option \"{}\"", key),
+					))
+				}
 			}
+		}
+		Ok(Args {
+			path,
+			include,
+			exclude,
+			embed,
+			compression,
 		})
-		.collect();
+	}
+}
+
+/// Parse a list of globs, such as `["*.css", "fonts/**"]`.
+fn parse_globs(input: syn::parse::ParseStream) -> syn::Result<Vec<syn::LitStr>> {
+	let content;
+	syn::bracketed!(content in input);
+	let globs =
+		content.parse_terminated::<_, syn::Token![,]>(<syn::LitStr as syn::parse::Parse>::parse)?;
+	Ok(globs.into_iter().collect())
+}
+
+/// Build the overrides for the `include` and `exclude` globs. This must match `FsDirectory`, so the same files are included whether they are embedded or not.
+fn overrides(path: &Path, args: &Args) -> syn::Result<ignore::overrides::Override> {
+	let mut overrides = ignore::overrides::OverrideBuilder::new(path);
+	for glob in args.include.iter() {
+		overrides
+			.add(&glob.value())
+			.map_err(|error| syn::Error::new(glob.span(), error))?;
+	}
+	for glob in args.exclude.iter() {
+		overrides
+			.add(&format!("!{}", glob.value()))
+			.map_err(|error| syn::Error::new(glob.span(), error))?;
+	}
+	overrides
+		.build()
+		.map_err(|error| syn::Error::new(args.path.span(), error))
+}
+
+fn embedded_directory(
+	path: &Path,
+	args: &Args,
+	overrides: ignore::overrides::Override,
+) -> syn::Result<proc_macro2::TokenStream> {
+	// This must match the walk in `FsDirectory`, so the same files are included whether they are embedded or not.
+	let mut absolute_paths: Vec<PathBuf> = Vec::new();
+	let mut ignore_file_paths: Vec<String> = Vec::new();
+	let walk = ignore::WalkBuilder::new(path)
+		.overrides(overrides)
+		.parents(false)
+		.require_git(false)
+		.git_global(false)
+		.git_exclude(false)
+		.build();
+	for entry in walk {
+		let entry = entry.map_err(|error| syn::Error::new(args.path.span(), error))?;
+		let file_type = entry.file_type();
+		if file_type.map(|file_type| file_type.is_file()) == Some(true) {
+			absolute_paths.push(entry.into_path());
+		} else if file_type.map(|file_type| file_type.is_dir()) == Some(true) {
+			for ignore_file_name in [".gitignore", ".ignore"] {
+				let ignore_file_path = entry.path().join(ignore_file_name);
+				if ignore_file_path.is_file() {
+					ignore_file_paths.push(ignore_file_path.to_str().unwrap().to_owned());
+				}
+			}
+		}
+	}
 	absolute_paths.sort();
 	let hashes = absolute_paths
 		.iter()
 		.map(|path| hash(std::fs::read(path).unwrap()));
+	let modified = absolute_paths.iter().map(|path| {
+		let modified = std::fs::metadata(path)
+			.and_then(|metadata| metadata.modified())
+			.ok()
+			.and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok());
+		match modified {
+			Some(modified) => {
+				let secs = modified.as_secs();
+				quote! { Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(#secs)) }
+			}
+			None => quote! { None },
+		}
+	});
 	let relative_paths = absolute_paths
 		.iter()
-		.map(|absolute_path| absolute_path.strip_prefix(&path).unwrap().to_owned());
-	let absolute_paths = absolute_paths
+		.map(|absolute_path| absolute_path.strip_prefix(path).unwrap().to_owned());
+	let data = absolute_paths
 		.iter()
-		.map(|path| path.to_str().unwrap().to_owned());
+		.map(|path| included_data(path, args.compression))
+		.collect::<syn::Result<Vec<_>>>()?;
 	let relative_paths = relative_paths.map(|path| path.to_str().unwrap().to_owned());
-	quote! {{
+	// Include the ignore files so cargo rebuilds when they change.
+	let code = quote! {{
+		#(const _: &[u8] = include_bytes!(#ignore_file_paths);)*
 		let mut map = std::collections::BTreeMap::new();
 		#({
 			let path = std::path::Path::new(#relative_paths);
-			let data = include_bytes!(#absolute_paths);
 			let file = sunfish::include_dir::IncludedFile {
-				data: data.as_ref(),
+				data: #data,
 				hash: #hashes,
+				modified: #modified,
 			};
 			map.insert(path, file);
 		})*
 		sunfish::include_dir::IncludedDirectory(map)
-	}}
+	}};
+	Ok(code)
+}
+
+/// Generate the `IncludedData` for the file at `path`. The file is stored compressed only if compression makes it smaller.
+fn included_data(
+	path: &Path,
+	compression: Option<Compression>,
+) -> syn::Result<proc_macro2::TokenStream> {
+	let path_string = path.to_str().unwrap();
+	let compressed = match compression {
+		Some(compression) => {
+			let data = std::fs::read(path).unwrap();
+			let compressed_path = compressed_path(&data, compression)?;
+			let compressed_len = std::fs::metadata(&compressed_path)
+				.map_err(|error| syn::Error::new(proc_macro2::Span::call_site(), error))?
+				.len();
+			if compressed_len < data.len() as u64 {
+				Some((compression, compressed_path, data.len() as u64))
+			} else {
+				None
+			}
+		}
+		None => None,
+	};
+	let code = match compressed {
+		Some((compression, compressed_path, len)) => {
+			let compression = match compression {
+				Compression::Gzip => quote! { sunfish::include_dir::Compression::Gzip },
+				Compression::Brotli => quote! { sunfish::include_dir::Compression::Brotli },
+			};
+			let compressed_path_string = compressed_path.to_str().unwrap();
+			// The file is only included to make cargo rebuild when it changes.
+			quote! {{
+				const _: &[u8] = include_bytes!(#path_string);
+				sunfish::include_dir::IncludedData::Compressed {
+					compression: #compression,
+					data: include_bytes!(#compressed_path_string),
+					len: #len,
+					decompressed: {
+						static DECOMPRESSED: sunfish::include_dir::Decompressed = sunfish::include_dir::Decompressed::new();
+						&DECOMPRESSED
+					},
+				}
+			}}
+		}
+		None => quote! {
+			sunfish::include_dir::IncludedData::Uncompressed(include_bytes!(#path_string).as_ref())
+		},
+	};
+	Ok(code)
+}
+
+/// Get the path of `data` compressed with `compression`, compressing it if it has not been compressed by a previous expansion. Compressed files are kept in the crate's `OUT_DIR`, or the temp directory if it has no build script, and named by the hash of the uncompressed data.
+fn compressed_path(data: &[u8], compression: Compression) -> syn::Result<PathBuf> {
+	let error = |error: std::io::Error| syn::Error::new(proc_macro2::Span::call_site(), error);
+	let dir = std::env::var_os("OUT_DIR")
+		.map(PathBuf::from)
+		.unwrap_or_else(std::env::temp_dir)
+		.join("sunfish_include_dir");
+	let extension = match compression {
+		Compression::Gzip => "gz",
+		Compression::Brotli => "br",
+	};
+	let path = dir.join(format!("{}.{}", hash(data), extension));
+	if path.exists() {
+		return Ok(path);
+	}
+	std::fs::create_dir_all(&dir).map_err(error)?;
+	// Write to a temporary file and rename it, so a concurrent expansion never includes a partially written file.
+	let temp_path = dir.join(format!(
+		"{}.{}.{}",
+		hash(data),
+		extension,
+		std::process::id()
+	));
+	std::fs::write(&temp_path, compress(data, compression)).map_err(error)?;
+	std::fs::rename(&temp_path, &path).map_err(error)?;
+	Ok(path)
+}
+
+fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+	match compression {
+		Compression::Gzip => {
+			let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+			gzip.write_all(data).unwrap();
+			gzip.finish().unwrap()
+		}
+		Compression::Brotli => {
+			let mut brotli = Vec::new();
+			{
+				let mut writer = brotli::CompressorWriter::new(&mut brotli, 4096, 11, 22);
+				writer.write_all(data).unwrap();
+			}
+			brotli
+		}
+	}
 }
 
 pub fn hash(bytes: impl AsRef<[u8]>) -> String {
@@ -73,3 +342,16 @@ pub fn hash(bytes: impl AsRef<[u8]>) -> String {
 	let hash = &hash[0..16];
 	hash.to_owned()
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_invalid_glob() {
+		for embed in ["always", "never", "auto"] {
+			let error = include_dir(quote! { ".", include = ["["], embed = #embed }).unwrap_err();
+			assert!(error.to_string().contains("unclosed character class"));
+		}
+	}
+}
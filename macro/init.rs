@@ -1,39 +1,141 @@
+use digest::Digest;
 use quote::{format_ident, quote};
 use std::path::{Path, PathBuf};
 
-pub fn init(_input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::TokenStream> {
+pub fn init(input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::TokenStream> {
 	let package_path = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
 	let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
 	let output_path = out_dir.join("output");
 	let output_path_string = output_path.display().to_string();
+	let asset_manifest_path_string = out_dir.join("assets.toml").display().to_string();
+	let client_paths_path_string = out_dir.join("client_paths.toml").display().to_string();
 	let routes_path = package_path.join("routes");
-	let server_entries = server_entries(&routes_path);
-	let routes_handler = routes_handler(&server_entries);
+	let server_entries = server_entries(&routes_path)?;
+	let routes_handler = routes_handler(&server_entries)?;
 	let routes = routes(&server_entries);
+	let not_found_route = error_route(&server_entries, NOT_FOUND_ROUTE_PATH);
+	let error_route = error_route(&server_entries, ERROR_ROUTE_PATH);
+	let build_id = build_id(&package_path);
 	let code = quote! {{
+		sunfish::load_asset_manifest(include_str!(#asset_manifest_path_string)).unwrap();
+		sunfish::load_client_paths(include_str!(#client_paths_path_string)).unwrap();
 		sunfish::Sunfish {
-			output: sunfish::include_dir!(#output_path_string),
+			output: sunfish::include_dir!(#output_path_string, #input),
 			routes_handler: #routes_handler,
 			routes: #routes,
+			cache_control: None,
+			content_types: std::collections::BTreeMap::new(),
+			not_found_route: #not_found_route,
+			error_route: #error_route,
+			middleware: Vec::new(),
+			export_options: Default::default(),
+			sitemap: None,
+			robots_txt: None,
+			trailing_slash: Default::default(),
+			build_id: #build_id.to_owned(),
 		}
 	}};
 	Ok(code)
 }
 
+/// Hash the path, length, and modified time of the files in the package, the packages it depends on by path, and the workspace's `Cargo.lock`.
+fn build_id(package_path: &Path) -> String {
+	let mut hash: sha2::Sha256 = Digest::new();
+	let mut package_paths = vec![package_path.to_owned()];
+	let mut visited_package_paths = std::collections::BTreeSet::new();
+	while let Some(package_path) = package_paths.pop() {
+		let package_path = match package_path.canonicalize() {
+			Ok(package_path) => package_path,
+			Err(_) => continue,
+		};
+		if !visited_package_paths.insert(package_path.clone()) {
+			continue;
+		}
+		package_paths.extend(path_dependencies(&package_path));
+	}
+	for package_path in visited_package_paths.iter() {
+		let walk = ignore::WalkBuilder::new(package_path)
+			.require_git(false)
+			.filter_entry(|entry| {
+				entry.depth() != 1
+					|| (entry.file_name() != "prerendered" && entry.file_name() != "target")
+			})
+			.sort_by_file_name(|a, b| a.cmp(b))
+			.build();
+		for entry in walk.filter_map(Result::ok) {
+			let metadata = match entry.metadata() {
+				Ok(metadata) if metadata.is_file() => metadata,
+				_ => continue,
+			};
+			let modified = metadata
+				.modified()
+				.ok()
+				.and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+				.unwrap_or_default();
+			hash.update(entry.path().to_string_lossy().as_bytes());
+			hash.update([0]);
+			hash.update(metadata.len().to_le_bytes());
+			hash.update(modified.as_nanos().to_le_bytes());
+		}
+	}
+	let lockfile_path = package_path
+		.ancestors()
+		.map(|path| path.join("Cargo.lock"))
+		.find(|path| path.exists());
+	if let Some(lockfile) = lockfile_path.and_then(|path| std::fs::read(path).ok()) {
+		hash.update(lockfile);
+	}
+	hex::encode(&hash.finalize()[0..8])
+}
+
+/// Get the paths of the packages that the package at `package_path` depends on by path.
+fn path_dependencies(package_path: &Path) -> Vec<PathBuf> {
+	let manifest = std::fs::read_to_string(package_path.join("Cargo.toml"))
+		.ok()
+		.and_then(|manifest| toml::from_str::<toml::Value>(&manifest).ok());
+	let manifest = match manifest {
+		Some(manifest) => manifest,
+		None => return Vec::new(),
+	};
+	let mut dependency_tables = Vec::new();
+	for table in [&manifest].into_iter().chain(
+		manifest
+			.get("target")
+			.and_then(|target| target.as_table())
+			.into_iter()
+			.flat_map(|target| target.values()),
+	) {
+		for name in ["dependencies", "build-dependencies"] {
+			if let Some(dependencies) = table.get(name).and_then(|table| table.as_table()) {
+				dependency_tables.push(dependencies);
+			}
+		}
+	}
+	dependency_tables
+		.into_iter()
+		.flat_map(|dependencies| dependencies.values())
+		.filter_map(|dependency| dependency.get("path")?.as_str())
+		.map(|path| package_path.join(path))
+		.collect()
+}
+
+const NOT_FOUND_ROUTE_PATH: &str = "/404";
+const ERROR_ROUTE_PATH: &str = "/500";
+
 #[derive(Debug)]
 struct ServerEntry {
 	package_name: String,
 	path_with_placeholders: String,
 }
 
-fn server_entries(routes_path: &Path) -> Vec<ServerEntry> {
+fn server_entries(routes_path: &Path) -> syn::Result<Vec<ServerEntry>> {
 	let glob = routes_path
 		.join("**")
 		.join("server")
 		.join("Cargo.toml")
 		.display()
 		.to_string();
-	let mut entries = glob::glob(&glob)
+	let entries = glob::glob(&glob)
 		.unwrap()
 		.filter_map(Result::ok)
 		.map(|manifest_path| {
@@ -58,8 +160,66 @@ fn server_entries(routes_path: &Path) -> Vec<ServerEntry> {
 			}
 		})
 		.collect::<Vec<_>>();
-	entries.sort_by(|a, b| a.path_with_placeholders.cmp(&b.path_with_placeholders));
-	entries
+	sort_server_entries(entries)
+}
+
+/// Sort the server entries by precedence and check that no two of them match the same paths.
+fn sort_server_entries(mut entries: Vec<ServerEntry>) -> syn::Result<Vec<ServerEntry>> {
+	// Sort the routes so that at the first segment where two routes differ, a static segment is matched before a placeholder, which is matched before a rest segment. This ensures a route never shadows a more specific one.
+	entries.sort_by(|a, b| {
+		segments(&a.path_with_placeholders)
+			.cmp(&segments(&b.path_with_placeholders))
+			.then_with(|| a.path_with_placeholders.cmp(&b.path_with_placeholders))
+	});
+	// Routes that overlap are allowed when one is more specific, because it always takes precedence. Routes that are equally specific are not.
+	for pair in entries.windows(2) {
+		if conflicts(
+			&segments(&pair[0].path_with_placeholders),
+			&segments(&pair[1].path_with_placeholders),
+		) {
+			let message = format!(
+				"the route \"{}\" in crate \"{}\" conflicts with the route \"{}\" in crate \"{}\"",
+				pair[0].path_with_placeholders,
+				pair[0].package_name,
+				pair[1].path_with_placeholders,
+				pair[1].package_name,
+			);
+			return Err(syn::Error::new(proc_macro2::Span::call_site(), message));
+		}
+	}
+	Ok(entries)
+}
+
+/// Whether two routes conflict. Routes with the same segments, ignoring the names of placeholders, match exactly the same paths. Routes that differ only in whether their rest segment is optional match the same paths except the one without the rest, so the required rest would shadow the optional rest everywhere else.
+fn conflicts(a: &[Segment], b: &[Segment]) -> bool {
+	match (a.split_last(), b.split_last()) {
+		(Some((Segment::Rest, a)), Some((Segment::OptionalRest, b)))
+		| (Some((Segment::OptionalRest, a)), Some((Segment::Rest, b))) => a == b,
+		_ => a == b,
+	}
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Segment<'a> {
+	Static(&'a str),
+	Placeholder,
+	Rest,
+	OptionalRest,
+}
+
+fn segments(path_with_placeholders: &str) -> Vec<Segment<'_>> {
+	path_components(path_with_placeholders)
+		.into_iter()
+		.map(|path_component| match rest_placeholder(path_component) {
+			Some((_, false)) => Segment::Rest,
+			Some((_, true)) => Segment::OptionalRest,
+			None if path_component == "_" || placeholder_name(path_component).is_some() => {
+				Segment::Placeholder
+			}
+			None if path_component == "index" => Segment::Static(""),
+			None => Segment::Static(path_component),
+		})
+		.collect()
 }
 
 fn path_with_placeholders(routes_path: &Path, manifest_path: &Path) -> String {
@@ -68,7 +228,7 @@ fn path_with_placeholders(routes_path: &Path, manifest_path: &Path) -> String {
 		.unwrap()
 		.parent()
 		.unwrap()
-		.strip_prefix(&routes_path)
+		.strip_prefix(routes_path)
 		.unwrap()
 		.components()
 		.map(|component| match component {
@@ -89,33 +249,80 @@ fn path_with_placeholders(routes_path: &Path, manifest_path: &Path) -> String {
 	path
 }
 
-fn routes_handler(server_entries: &[ServerEntry]) -> proc_macro2::TokenStream {
-	let match_arms = server_entries.iter().map(|server_entry| {
-		let package_name = &server_entry.package_name;
-		let server_package_name_ident = format_ident!("{}", server_entry.package_name);
-		let path_components = server_entry
-			.path_with_placeholders
-			.split('/')
-			.into_iter()
-			.skip(1)
-			.map(|path_component| match path_component {
-				"_" => quote! { _ },
-				"index" => quote! { "" },
-				path_component => quote! { #path_component },
-			})
-			.collect::<Vec<_>>();
-		quote! {
-			#[cfg(feature = #package_name)]
-			[#(#path_components),*] => {
-				use futures::{Future, FutureExt, TryFutureExt};
-				#server_package_name_ident::init().handle(request).map_ok(|response| Some(response)).boxed()
+fn routes_handler(server_entries: &[ServerEntry]) -> syn::Result<proc_macro2::TokenStream> {
+	let match_arms = server_entries
+		.iter()
+		.filter(|server_entry| {
+			let path_with_placeholders = server_entry.path_with_placeholders.as_str();
+			path_with_placeholders != NOT_FOUND_ROUTE_PATH
+				&& path_with_placeholders != ERROR_ROUTE_PATH
+		})
+		.map(|server_entry| {
+			let package_name = &server_entry.package_name;
+			let server_package_name_ident = format_ident!("{}", server_entry.package_name);
+			let path_components = path_components(&server_entry.path_with_placeholders);
+			let mut patterns = Vec::new();
+			let mut guard = None;
+			let mut param_names = Vec::new();
+			let mut param_values = Vec::new();
+			for (index, path_component) in path_components.iter().enumerate() {
+				let param_ident = format_ident!("param_{}", index);
+				if let Some((param_name, optional)) = rest_placeholder(path_component) {
+					if index != path_components.len() - 1 {
+						return Err(syn::Error::new(
+							proc_macro2::Span::call_site(),
+							format!(
+								"the rest segment in route \"{}\" must be its last segment",
+								server_entry.path_with_placeholders
+							),
+						));
+					}
+					// A required rest segment must match at least one non-empty path component.
+					if !optional {
+						guard =
+							Some(quote! { if !#param_ident.is_empty() && #param_ident != [""] });
+					}
+					patterns.push(quote! { #param_ident @ .. });
+					param_names.push(param_name.to_owned());
+					param_values.push(quote! { #param_ident.join("/") });
+					continue;
+				}
+				let pattern = match *path_component {
+					"_" => quote! { _ },
+					"index" => quote! { "" },
+					path_component => match placeholder_name(path_component) {
+						Some(param_name) => {
+							param_names.push(param_name.to_owned());
+							param_values.push(quote! { #param_ident.to_string() });
+							quote! { #param_ident }
+						}
+						None => quote! { #path_component },
+					},
+				};
+				patterns.push(pattern);
 			}
-		}
-	});
-	quote! {
+			let match_arm = quote! {
+				#[cfg(feature = #package_name)]
+				[#(#patterns),*] #guard => {
+					use futures::{Future, FutureExt, TryFutureExt};
+					let params: std::collections::BTreeMap<String, String> = vec![
+						#((#param_names.to_owned(), #param_values)),*
+					].into_iter().collect();
+					request.extensions_mut().insert(sunfish::RouteParams(params));
+					#server_package_name_ident::init().handle(request).map_ok(|response| Some(response)).boxed()
+				}
+			};
+			Ok(match_arm)
+		})
+		.collect::<syn::Result<Vec<_>>>()?;
+	let code = quote! {
 		Box::new(|request| {
 			let path = request.uri().path();
-			let path_components: Vec<_> = path.split('/').skip(1).collect();
+			let mut path_components: Vec<_> = path.split('/').skip(1).collect();
+			// Ignore a trailing slash. Sunfish::handle redirects according to the trailing slash policy before routing.
+			if path_components.len() > 1 && path_components.last() == Some(&"") {
+				path_components.pop();
+			}
 			match path_components.as_slice() {
 				#(#match_arms)*
 				_ => {
@@ -124,23 +331,252 @@ fn routes_handler(server_entries: &[ServerEntry]) -> proc_macro2::TokenStream {
 				}
 			}
 		})
+	};
+	Ok(code)
+}
+
+/// Split a route's path into its components, ignoring a trailing slash, so `/docs/` and `/docs` are the same route. This must match the way the generated routes handler splits request paths.
+fn path_components(path_with_placeholders: &str) -> Vec<&str> {
+	let mut path_components = path_with_placeholders
+		.split('/')
+		.skip(1)
+		.collect::<Vec<_>>();
+	if path_components.len() > 1 && path_components.last() == Some(&"") {
+		path_components.pop();
+	}
+	path_components
+}
+
+/// Get the name of a placeholder path component, which is either `_name` or `[name]`. A bare `_` is an unnamed placeholder.
+fn placeholder_name(path_component: &str) -> Option<&str> {
+	if rest_placeholder(path_component).is_some() {
+		return None;
+	}
+	if let Some(name) = path_component
+		.strip_prefix('[')
+		.and_then(|path_component| path_component.strip_suffix(']'))
+	{
+		Some(name)
+	} else {
+		path_component
+			.strip_prefix('_')
+			.filter(|name| !name.is_empty())
+	}
+}
+
+/// Get the name of a rest path component and whether it is optional. `[...name]` matches one or more trailing path components and `[[...name]]` matches zero or more.
+fn rest_placeholder(path_component: &str) -> Option<(&str, bool)> {
+	if let Some(name) = path_component
+		.strip_prefix("[[...")
+		.and_then(|path_component| path_component.strip_suffix("]]"))
+	{
+		Some((name, true))
+	} else {
+		path_component
+			.strip_prefix("[...")
+			.and_then(|path_component| path_component.strip_suffix(']'))
+			.map(|name| (name, false))
 	}
 }
 
 fn routes(server_entries: &[ServerEntry]) -> proc_macro2::TokenStream {
 	let routes = server_entries
 		.iter()
-		.map(|server_entry| {
-			let package_name = server_entry.package_name.to_owned();
-			let package_name_ident = format_ident!("{}", package_name);
-			let path_with_placeholders = &server_entry.path_with_placeholders;
-			quote! {
-				sunfish::RouteInitializer {
-					path_with_placeholders: #path_with_placeholders.to_owned(),
-					init: #package_name_ident::init,
-				}
-			}
-		})
+		.map(route_initializer)
 		.collect::<Vec<_>>();
 	quote! { vec![#(#routes),*] }
 }
+
+/// Generate the route initializer for the server crate at `path`, which is served when no other route matches or a route fails rather than at its own path.
+fn error_route(server_entries: &[ServerEntry], path: &str) -> proc_macro2::TokenStream {
+	let server_entry = server_entries
+		.iter()
+		.find(|server_entry| server_entry.path_with_placeholders == path);
+	match server_entry {
+		Some(server_entry) => {
+			let route_initializer = route_initializer(server_entry);
+			quote! { Some(#route_initializer) }
+		}
+		None => quote! { None },
+	}
+}
+
+fn route_initializer(server_entry: &ServerEntry) -> proc_macro2::TokenStream {
+	let package_name = server_entry.package_name.to_owned();
+	let package_name_ident = format_ident!("{}", package_name);
+	let path_with_placeholders = &server_entry.path_with_placeholders;
+	quote! {
+		sunfish::RouteInitializer {
+			path_with_placeholders: #path_with_placeholders.to_owned(),
+			init: #package_name_ident::init,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn server_entry(path_with_placeholders: &str) -> ServerEntry {
+		server_entry_in_package("route_server", path_with_placeholders)
+	}
+
+	fn server_entry_in_package(package_name: &str, path_with_placeholders: &str) -> ServerEntry {
+		ServerEntry {
+			package_name: package_name.to_owned(),
+			path_with_placeholders: path_with_placeholders.to_owned(),
+		}
+	}
+
+	#[test]
+	fn test_segments_order() {
+		let mut paths = vec![
+			"/docs/[[...path]]",
+			"/docs/[...path]",
+			"/docs/_",
+			"/docs/intro",
+			"/[...path]",
+			"/_name",
+			"/about",
+			"/",
+		];
+		paths.sort_by(|a, b| segments(a).cmp(&segments(b)));
+		assert_eq!(
+			paths,
+			vec![
+				"/",
+				"/about",
+				"/docs/intro",
+				"/docs/_",
+				"/docs/[...path]",
+				"/docs/[[...path]]",
+				"/_name",
+				"/[...path]",
+			]
+		);
+	}
+
+	#[test]
+	fn test_segments() {
+		assert_eq!(segments("/"), vec![Segment::Static("")]);
+		assert_eq!(segments("/docs/"), vec![Segment::Static("docs")]);
+		assert_eq!(
+			segments("/users/_id/[name]/_"),
+			vec![
+				Segment::Static("users"),
+				Segment::Placeholder,
+				Segment::Placeholder,
+				Segment::Placeholder,
+			]
+		);
+		assert_eq!(
+			segments("/a/[...rest]"),
+			vec![Segment::Static("a"), Segment::Rest]
+		);
+		assert_eq!(
+			segments("/a/[[...rest]]"),
+			vec![Segment::Static("a"), Segment::OptionalRest]
+		);
+	}
+
+	#[test]
+	fn test_rest_guard() {
+		let code = routes_handler(&[server_entry("/docs/[...path]")])
+			.unwrap()
+			.to_string();
+		assert!(code.contains(
+			&quote! { [ "docs" , param_1 @ .. ] if !param_1.is_empty() && param_1 != [""] }
+				.to_string()
+		));
+	}
+
+	#[test]
+	fn test_optional_rest_guard() {
+		let code = routes_handler(&[server_entry("/docs/[[...path]]")])
+			.unwrap()
+			.to_string();
+		assert!(code.contains(&quote! { [ "docs" , param_1 @ .. ] => }.to_string()));
+		assert!(!code.contains("is_empty"));
+	}
+
+	#[test]
+	fn test_rest_segment_must_be_last() {
+		let error = routes_handler(&[server_entry("/[...path]/edit")]).unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"the rest segment in route \"/[...path]/edit\" must be its last segment"
+		);
+	}
+
+	#[test]
+	fn test_placeholder_conflict() {
+		let entries = vec![
+			server_entry_in_package("users_id_server", "/users/_id"),
+			server_entry_in_package("users_name_server", "/users/[name]"),
+		];
+		let error = sort_server_entries(entries).unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"the route \"/users/[name]\" in crate \"users_name_server\" conflicts with the route \"/users/_id\" in crate \"users_id_server\""
+		);
+	}
+
+	#[test]
+	fn test_index_conflict() {
+		let routes_path = Path::new("/app/routes");
+		let index_path = path_with_placeholders(
+			routes_path,
+			Path::new("/app/routes/docs/index/server/Cargo.toml"),
+		);
+		let bare_path =
+			path_with_placeholders(routes_path, Path::new("/app/routes/docs/server/Cargo.toml"));
+		let entries = vec![
+			server_entry_in_package("docs_index_server", &index_path),
+			server_entry_in_package("docs_server", &bare_path),
+		];
+		let error = sort_server_entries(entries).unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"the route \"/docs\" in crate \"docs_server\" conflicts with the route \"/docs/\" in crate \"docs_index_server\""
+		);
+	}
+
+	#[test]
+	fn test_rest_conflict() {
+		let entries = vec![
+			server_entry_in_package("docs_optional_server", "/docs/[[...path]]"),
+			server_entry_in_package("docs_server", "/docs/[...path]"),
+		];
+		let error = sort_server_entries(entries).unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"the route \"/docs/[...path]\" in crate \"docs_server\" conflicts with the route \"/docs/[[...path]]\" in crate \"docs_optional_server\""
+		);
+	}
+
+	#[test]
+	fn test_no_conflict() {
+		let entries = vec![
+			server_entry("/users/_id"),
+			server_entry("/users/new"),
+			server_entry("/users/[...path]"),
+			server_entry("/docs"),
+			server_entry("/docs/[[...path]]"),
+		];
+		let entries = sort_server_entries(entries).unwrap();
+		let paths = entries
+			.iter()
+			.map(|entry| entry.path_with_placeholders.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			paths,
+			vec![
+				"/docs",
+				"/docs/[[...path]]",
+				"/users/new",
+				"/users/_id",
+				"/users/[...path]"
+			]
+		);
+	}
+}
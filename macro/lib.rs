@@ -1,6 +1,14 @@
 mod include_dir;
 mod init;
 
+/// Include the files in a directory, such as `include_dir!("path/to/dir")`. Hidden files and files ignored by `.gitignore` or `.ignore` files in the directory are left out. These options can follow the path:
+///
+/// - `include = ["*.css", "fonts/**"]` includes only the files matching at least one of the globs.
+/// - `exclude = ["*.map"]` leaves out the files matching any of the globs, even if they match an `include` glob.
+/// - `embed = "auto"`, the default, reads the files from the filesystem in debug builds and embeds them in the binary in release builds. `embed = "always"` always embeds them and `embed = "never"` always reads them from the filesystem, at the absolute path the directory had when the crate was compiled unless `IncludeDir::set_fs_path` is called.
+/// - `compression = "gzip"` or `compression = "brotli"` stores each embedded file compressed if that makes it smaller.
+///
+/// Cargo does not rebuild the crate when a file is added to or removed from the directory. Call `sunfish::include_dir::rerun_if_changed` from a build script to rebuild it then.
 #[proc_macro]
 pub fn include_dir(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 	include_dir::include_dir(input.into())
@@ -8,6 +16,18 @@ pub fn include_dir(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 		.into()
 }
 
+/// Create a `Sunfish` from the server crates under `routes/`. Each `routes/<path>/server` crate serves `<path>`, where `index` matches an empty path component and the following directory names are placeholders:
+///
+/// - `_` matches any single path component.
+/// - `_name` or `[name]` matches any single path component and passes it to the handler as the param `name`.
+/// - `[...name]` matches one or more trailing path components and passes them to the handler joined by `/` as the param `name`. It must be the last segment.
+/// - `[[...name]]` is like `[...name]` but also matches zero trailing path components.
+///
+/// The server crates in `routes/404` and `routes/500` are not served at their own paths. Instead, `Sunfish::handle` serves `routes/404` with status 404 when no route or file matches a request and `routes/500` with status 500 when a route returns an error.
+///
+/// Routes are compared segment by segment, and at the first segment where two routes differ, a static segment takes precedence over a placeholder, which takes precedence over a `[...name]` segment, which takes precedence over a `[[...name]]` segment. For example, `routes/about` takes precedence over `routes/_`, and `routes/docs/intro` takes precedence over `routes/docs/[...path]`. These overlaps are allowed on purpose, as is `routes/docs` next to `routes/docs/[[...path]]`, which serves `/docs` with `routes/docs`. Two routes that differ only in the names of their placeholders, such as `routes/users/_id` and `routes/users/[name]`, or only in whether their rest segment is optional, such as `routes/docs/[...path]` and `routes/docs/[[...path]]`, are a compile error.
+///
+/// The `Sunfish`'s `output` is included with `include_dir`, and `init` takes the same options, such as `init!(compression = "brotli")` to store the embedded output compressed or `init!(embed = "always")` to embed it in debug builds too.
 #[proc_macro]
 pub fn init(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 	init::init(input.into())
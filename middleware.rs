@@ -0,0 +1,86 @@
+use crate::Sunfish;
+use anyhow::Result;
+use futures::FutureExt;
+use std::{future::Future, pin::Pin};
+
+/// Middleware runs around `Sunfish::handle`. It can modify the request before calling `next`, modify the response `next` returns, or respond without calling `next` at all.
+pub trait Middleware: Send + Sync {
+	fn handle<'a>(
+		&'a self,
+		request: &'a mut http::Request<hyper::Body>,
+		next: Next<'a>,
+	) -> MiddlewareOutput<'a>;
+}
+
+pub type MiddlewareOutput<'a> =
+	Pin<Box<dyn 'a + Send + Future<Output = Result<Option<http::Response<hyper::Body>>>>>>;
+
+impl<F> Middleware for F
+where
+	F: Send
+		+ Sync
+		+ for<'a> Fn(&'a mut http::Request<hyper::Body>, Next<'a>) -> MiddlewareOutput<'a>,
+{
+	fn handle<'a>(
+		&'a self,
+		request: &'a mut http::Request<hyper::Body>,
+		next: Next<'a>,
+	) -> MiddlewareOutput<'a> {
+		self(request, next)
+	}
+}
+
+/// Create a middleware from a closure. This is only needed so the closure's signature is inferred with the right lifetimes.
+pub fn from_fn<F>(f: F) -> F
+where
+	F: Send
+		+ Sync
+		+ for<'a> Fn(&'a mut http::Request<hyper::Body>, Next<'a>) -> MiddlewareOutput<'a>,
+{
+	f
+}
+
+/// A middleware that only runs for requests whose path is `path_prefix` or is under it.
+pub struct ScopedMiddleware {
+	pub path_prefix: String,
+	pub middleware: Box<dyn Middleware>,
+}
+
+impl ScopedMiddleware {
+	fn matches(&self, path: &str) -> bool {
+		let path_prefix = self.path_prefix.trim_end_matches('/');
+		match path.strip_prefix(path_prefix) {
+			Some(rest) => rest.is_empty() || rest.starts_with('/'),
+			None => false,
+		}
+	}
+}
+
+/// The rest of the middleware chain, followed by the handler.
+pub struct Next<'a> {
+	pub(crate) sunfish: &'a Sunfish,
+	pub(crate) middleware: &'a [ScopedMiddleware],
+}
+
+impl<'a> Next<'a> {
+	pub fn run<'b>(self, request: &'b mut http::Request<hyper::Body>) -> MiddlewareOutput<'b>
+	where
+		'a: 'b,
+	{
+		let path = request.uri().path();
+		let index = self
+			.middleware
+			.iter()
+			.position(|middleware| middleware.matches(path));
+		match index {
+			Some(index) => {
+				let next = Next {
+					sunfish: self.sunfish,
+					middleware: &self.middleware[index + 1..],
+				};
+				self.middleware[index].middleware.handle(request, next)
+			}
+			None => self.sunfish.handle_without_middleware(request).boxed(),
+		}
+	}
+}
@@ -0,0 +1,221 @@
+use crate::include_dir::{FileStream, FsOrIncludedFile};
+use futures::StreamExt;
+use hyper::body::Bytes;
+use std::ops::Range;
+
+/// The most ranges a single request may ask for. Requests with more ranges are served the whole file, so a request cannot make the server send the same bytes many times over.
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Ranges {
+	Satisfiable(Vec<Range<u64>>),
+	Unsatisfiable,
+}
+
+/// Parse the value of a `Range` header for a file of `len` bytes. This returns `None` if the header is invalid or uses a unit other than bytes, in which case the header should be ignored.
+pub(crate) fn parse_range(range: &str, len: u64) -> Option<Ranges> {
+	let range = range.trim().strip_prefix("bytes=")?;
+	let mut ranges = Vec::new();
+	let mut count = 0;
+	for range in range.split(',') {
+		let range = range.trim();
+		if range.is_empty() {
+			continue;
+		}
+		count += 1;
+		if count > MAX_RANGES {
+			return None;
+		}
+		let (start, end) = range.split_once('-')?;
+		let (start, end) = (start.trim(), end.trim());
+		let range = if start.is_empty() {
+			// A suffix range selects the last `end` bytes of the file.
+			let suffix_len: u64 = end.parse().ok()?;
+			if suffix_len == 0 || len == 0 {
+				continue;
+			}
+			len.saturating_sub(suffix_len)..len
+		} else {
+			let start: u64 = start.parse().ok()?;
+			let end = if end.is_empty() {
+				len
+			} else {
+				let end: u64 = end.parse().ok()?;
+				if end < start {
+					return None;
+				}
+				end.saturating_add(1).min(len)
+			};
+			if start >= len {
+				continue;
+			}
+			start..end
+		};
+		ranges.push(range);
+	}
+	if count == 0 {
+		return None;
+	}
+	if ranges.is_empty() {
+		Some(Ranges::Unsatisfiable)
+	} else {
+		Some(Ranges::Satisfiable(ranges))
+	}
+}
+
+pub(crate) fn content_range(range: &Range<u64>, len: u64) -> String {
+	format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+/// The body of a `multipart/byteranges` response, which has a part with its own headers for each range.
+pub(crate) struct Multipart {
+	boundary: String,
+	parts: Vec<(String, Range<u64>)>,
+	closing: String,
+}
+
+impl Multipart {
+	pub fn new(ranges: &[Range<u64>], len: u64, content_type: Option<&str>) -> Multipart {
+		let boundary = boundary();
+		let parts = ranges
+			.iter()
+			.map(|range| {
+				let mut part_headers = format!("\r\n--{}\r\n", boundary);
+				if let Some(content_type) = content_type {
+					part_headers.push_str(&format!("Content-Type: {}\r\n", content_type));
+				}
+				part_headers.push_str(&format!(
+					"Content-Range: {}\r\n\r\n",
+					content_range(range, len)
+				));
+				(part_headers, range.clone())
+			})
+			.collect();
+		let closing = format!("\r\n--{}--\r\n", boundary);
+		Multipart {
+			boundary,
+			parts,
+			closing,
+		}
+	}
+
+	pub fn content_length(&self) -> u64 {
+		let parts_len: u64 = self
+			.parts
+			.iter()
+			.map(|(part_headers, range)| part_headers.len() as u64 + (range.end - range.start))
+			.sum();
+		parts_len + self.closing.len() as u64
+	}
+
+	pub fn content_type(&self) -> String {
+		format!("multipart/byteranges; boundary={}", self.boundary)
+	}
+
+	/// Stream the body, reading each range from the file as it is sent.
+	pub fn into_stream(self, file: &FsOrIncludedFile) -> FileStream {
+		let parts = self.parts.into_iter().map(|(part_headers, range)| {
+			let part_headers = futures::stream::once(async move { Ok(Bytes::from(part_headers)) });
+			part_headers.chain(file.stream_range(range))
+		});
+		let closing = futures::stream::once(async move { Ok(Bytes::from(self.closing)) });
+		let parts: Vec<_> = parts.map(StreamExt::boxed).collect();
+		Box::pin(futures::stream::iter(parts).flatten().chain(closing))
+	}
+}
+
+fn boundary() -> String {
+	let nanos = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_nanos();
+	format!("sunfish_{:032x}", nanos)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::include_dir::{IncludedData, IncludedFile};
+	use futures::TryStreamExt;
+
+	fn satisfiable(range: Range<u64>) -> Option<Ranges> {
+		Some(Ranges::Satisfiable(vec![range]))
+	}
+
+	#[test]
+	fn test_parse_range() {
+		assert_eq!(parse_range("bytes=0-4", 10), satisfiable(0..5));
+		assert_eq!(parse_range("bytes=2-100", 10), satisfiable(2..10));
+		assert_eq!(
+			parse_range("bytes=0-0, 5-6", 10),
+			Some(Ranges::Satisfiable(vec![0..1, 5..7]))
+		);
+		assert_eq!(parse_range("items=0-4", 10), None);
+		assert_eq!(parse_range("bytes=a-4", 10), None);
+		assert_eq!(parse_range("bytes=", 10), None);
+	}
+
+	#[test]
+	fn test_parse_suffix_range() {
+		assert_eq!(parse_range("bytes=-3", 10), satisfiable(7..10));
+		assert_eq!(parse_range("bytes=-20", 10), satisfiable(0..10));
+		assert_eq!(parse_range("bytes=-0", 10), Some(Ranges::Unsatisfiable));
+	}
+
+	#[test]
+	fn test_parse_open_ended_range() {
+		assert_eq!(parse_range("bytes=4-", 10), satisfiable(4..10));
+		assert_eq!(parse_range("bytes=10-", 10), Some(Ranges::Unsatisfiable));
+	}
+
+	#[test]
+	fn test_parse_range_end_before_start() {
+		assert_eq!(parse_range("bytes=5-2", 10), None);
+		assert_eq!(parse_range("bytes=0-1, 5-2", 10), None);
+	}
+
+	#[test]
+	fn test_parse_too_many_ranges() {
+		let ranges = |count: usize| {
+			let ranges = (0..count)
+				.map(|index| format!("{}-{}", index, index))
+				.collect::<Vec<_>>();
+			format!("bytes={}", ranges.join(","))
+		};
+		assert!(matches!(
+			parse_range(&ranges(MAX_RANGES), 100),
+			Some(Ranges::Satisfiable(ranges)) if ranges.len() == MAX_RANGES
+		));
+		assert_eq!(parse_range(&ranges(MAX_RANGES + 1), 100), None);
+	}
+
+	#[test]
+	fn test_parse_range_empty_file() {
+		assert_eq!(parse_range("bytes=0-", 0), Some(Ranges::Unsatisfiable));
+		assert_eq!(parse_range("bytes=-5", 0), Some(Ranges::Unsatisfiable));
+	}
+
+	#[test]
+	fn test_multipart_content_length() {
+		let file = FsOrIncludedFile::Included(IncludedFile {
+			data: IncludedData::Uncompressed(b"0123456789"),
+			hash: "0123456789abcdef",
+			modified: None,
+		});
+		let ranges = [0..2, 4..5, 7..10];
+		let multipart = Multipart::new(&ranges, 10, Some("text/plain; charset=utf-8"));
+		let content_length = multipart.content_length();
+		let body = futures::executor::block_on(multipart.into_stream(&file).try_fold(
+			Vec::new(),
+			|mut body, chunk| async move {
+				body.extend_from_slice(&chunk);
+				Ok(body)
+			},
+		))
+		.unwrap();
+		assert_eq!(body.len() as u64, content_length);
+		let body = String::from_utf8(body).unwrap();
+		assert!(body.contains("Content-Range: bytes 4-4/10\r\n\r\n4\r\n"));
+		assert!(body.ends_with("--\r\n"));
+	}
+}
@@ -0,0 +1,91 @@
+use crate::Sunfish;
+use anyhow::{anyhow, Result};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+impl Sunfish {
+	/// Serve on the address in the `HOST` and `PORT` environment variables, which `watchserve` sets for the process it runs. `HOST` defaults to `0.0.0.0` and `PORT` defaults to `8080`.
+	pub async fn serve_from_env(self) -> Result<()> {
+		let host = match std::env::var("HOST") {
+			Ok(host) => host
+				.parse()
+				.map_err(|_| anyhow!("HOST \"{}\" is not a valid ip address", host))?,
+			Err(_) => std::net::Ipv4Addr::UNSPECIFIED.into(),
+		};
+		let port = match std::env::var("PORT") {
+			Ok(port) => port
+				.parse()
+				.map_err(|_| anyhow!("PORT \"{}\" is not a valid port", port))?,
+			Err(_) => 8080,
+		};
+		self.serve(SocketAddr::new(host, port)).await
+	}
+
+	/// Serve on `addr` until the process receives SIGINT or SIGTERM, then finish the requests in flight and return.
+	pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+		let sunfish = Arc::new(self);
+		let service = hyper::service::make_service_fn(|_| {
+			let sunfish = sunfish.clone();
+			async move {
+				Ok::<_, Infallible>(hyper::service::service_fn(
+					move |request: http::Request<hyper::Body>| {
+						let sunfish = sunfish.clone();
+						async move { Ok::<_, Infallible>(handle(&sunfish, request).await) }
+					},
+				))
+			}
+		});
+		let server = hyper::Server::try_bind(&addr)?
+			.serve(service)
+			.with_graceful_shutdown(shutdown_signal());
+		tracing::info!("serving at http://{}", addr);
+		server.await?;
+		Ok(())
+	}
+}
+
+async fn handle(
+	sunfish: &Sunfish,
+	mut request: http::Request<hyper::Body>,
+) -> http::Response<hyper::Body> {
+	let method = request.method().clone();
+	let path = request.uri().path().to_owned();
+	let start = std::time::Instant::now();
+	let response = match sunfish.handle(&mut request).await {
+		Ok(Some(response)) => response,
+		Ok(None) => http::Response::builder()
+			.status(http::StatusCode::NOT_FOUND)
+			.body(hyper::Body::from("not found"))
+			.unwrap(),
+		Err(error) => {
+			tracing::error!("{:?}", error);
+			http::Response::builder()
+				.status(http::StatusCode::INTERNAL_SERVER_ERROR)
+				.body(hyper::Body::from("internal server error"))
+				.unwrap()
+		}
+	};
+	tracing::info!(
+		%method,
+		%path,
+		status = response.status().as_u16(),
+		duration = ?start.elapsed(),
+		"request",
+	);
+	response
+}
+
+async fn shutdown_signal() {
+	let interrupt = tokio::signal::ctrl_c();
+	#[cfg(unix)]
+	let terminate = async {
+		tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+			.unwrap()
+			.recv()
+			.await;
+	};
+	#[cfg(not(unix))]
+	let terminate = futures::future::pending::<()>();
+	futures::pin_mut!(interrupt, terminate);
+	futures::future::select(interrupt, terminate).await;
+	tracing::info!("shutting down");
+}
@@ -0,0 +1,146 @@
+use crate::{has_placeholders, Route, Sunfish};
+use anyhow::Result;
+use std::path::Path;
+
+pub struct SitemapOptions {
+	/// The url the site is served at, such as `https://example.com`, which is prepended to the path of each page.
+	pub base_url: String,
+}
+
+/// The optional fields of a page's entry in the sitemap.
+#[derive(Clone, Debug, Default)]
+pub struct SitemapEntry {
+	/// The date the page was last modified, in W3C datetime format, such as `2022-01-31`.
+	pub lastmod: Option<String>,
+	/// The priority of the page relative to the other pages on the site, from 0.0 to 1.0.
+	pub priority: Option<f32>,
+}
+
+impl Sunfish {
+	/// Render the sitemap of the static pages, or return `None` if `sitemap` is not set.
+	pub async fn sitemap(&self) -> Result<Option<String>> {
+		let sitemap_options = match self.sitemap.as_ref() {
+			Some(sitemap_options) => sitemap_options,
+			None => return Ok(None),
+		};
+		let mut entries = Vec::new();
+		for route_initializer in self.routes.iter() {
+			let route_path = &route_initializer.path_with_placeholders;
+			let route = (route_initializer.init)();
+			let paths = match &route {
+				Route::Static {
+					paths: Some(paths), ..
+				} => paths().await?,
+				Route::Static { paths: None, .. } => vec![route_path.clone()],
+				Route::Dynamic { .. } => continue,
+			};
+			for path in paths {
+				if let Some(entry) = self.sitemap_entry(route_path, &route, &path) {
+					entries.push((self.trailing_slash.canonical_path(&path), entry));
+				}
+			}
+		}
+		Ok(Some(sitemap_xml(&sitemap_options.base_url, &entries)))
+	}
+
+	/// Render robots.txt, or return `None` if `robots_txt` is not set. If `sitemap` is set, this adds a line with the url of the sitemap.
+	pub fn robots_txt(&self) -> Option<String> {
+		let mut robots_txt = self.robots_txt.clone()?;
+		if let Some(sitemap_options) = self.sitemap.as_ref() {
+			if !robots_txt.is_empty() && !robots_txt.ends_with('\n') {
+				robots_txt.push('\n');
+			}
+			robots_txt.push_str(&format!(
+				"Sitemap: {}/sitemap.xml\n",
+				sitemap_options.base_url.trim_end_matches('/')
+			));
+		}
+		Some(robots_txt)
+	}
+
+	/// Get the sitemap entry for a page, or `None` if the page should not be in the sitemap. The not found and error pages are never in the sitemap, and neither are pages for routes with placeholders that do not list their paths.
+	pub(crate) fn sitemap_entry(
+		&self,
+		route_path: &str,
+		route: &Route,
+		path: &str,
+	) -> Option<SitemapEntry> {
+		if self.is_error_route(route_path) || (path == route_path && has_placeholders(route_path)) {
+			return None;
+		}
+		match route {
+			Route::Static {
+				sitemap: Some(sitemap),
+				..
+			} => sitemap(path),
+			Route::Static { sitemap: None, .. } => Some(SitemapEntry::default()),
+			Route::Dynamic { .. } => None,
+		}
+	}
+
+	pub(crate) async fn serve_sitemap_or_robots_txt(
+		&self,
+		request: &http::Request<hyper::Body>,
+	) -> Result<Option<http::Response<hyper::Body>>> {
+		if request.method() != http::Method::GET {
+			return Ok(None);
+		}
+		let path = request.uri().path();
+		let body = match path {
+			"/sitemap.xml" => match self.sitemap().await? {
+				Some(sitemap) => sitemap,
+				None => return Ok(None),
+			},
+			"/robots.txt" => match self.robots_txt() {
+				Some(robots_txt) => robots_txt,
+				None => return Ok(None),
+			},
+			_ => return Ok(None),
+		};
+		let response = http::Response::builder()
+			.status(http::StatusCode::OK)
+			.header(
+				http::header::CONTENT_TYPE,
+				self.content_type(Path::new(path)).unwrap(),
+			)
+			.body(hyper::Body::from(body))
+			.unwrap();
+		Ok(Some(response))
+	}
+}
+
+pub(crate) fn sitemap_xml(base_url: &str, entries: &[(String, SitemapEntry)]) -> String {
+	let base_url = base_url.trim_end_matches('/');
+	let mut sitemap = String::new();
+	sitemap.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+	sitemap.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+	for (path, entry) in entries {
+		sitemap.push_str("\t<url>\n");
+		let loc = format!("{}{}", base_url, path);
+		sitemap.push_str(&format!("\t\t<loc>{}</loc>\n", escape_xml(&loc)));
+		if let Some(lastmod) = entry.lastmod.as_ref() {
+			sitemap.push_str(&format!("\t\t<lastmod>{}</lastmod>\n", escape_xml(lastmod)));
+		}
+		if let Some(priority) = entry.priority {
+			sitemap.push_str(&format!("\t\t<priority>{}</priority>\n", priority));
+		}
+		sitemap.push_str("\t</url>\n");
+	}
+	sitemap.push_str("</urlset>\n");
+	sitemap
+}
+
+fn escape_xml(value: &str) -> String {
+	let mut escaped = String::with_capacity(value.len());
+	for c in value.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&apos;"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
@@ -91,8 +91,8 @@ pub async fn run(config: Config) {
 				let sh = which("sh").unwrap();
 				let child = std::process::Command::new(sh)
 					.args(vec!["-c", &command])
-					.env("HOST", &child_host.to_string())
-					.env("PORT", &child_port.to_string())
+					.env("HOST", child_host.to_string())
+					.env("PORT", child_port.to_string())
 					.spawn()
 					.unwrap();
 				*state.lock().await = State::Building {